@@ -0,0 +1,172 @@
+use std::fmt;
+
+use druid::im::Vector;
+use druid::{Data, Lens};
+use mailparse::{addrparse_header, MailAddr, MailHeader, MailHeaderMap};
+
+/// A single mailbox from an address header, with any RFC 2047 encoded words decoded.
+#[derive(Clone, Data, Lens, Debug, PartialEq, Eq, Hash)]
+pub struct Address {
+    pub name: Option<String>,
+    pub email: String,
+}
+
+impl Address {
+    pub fn new(name: Option<String>, email: impl Into<String>) -> Self {
+        Self {
+            name: name.filter(|name| !name.trim().is_empty()),
+            email: email.into(),
+        }
+    }
+
+    /// The name to show in the UI: the display name if there is one, the address otherwise.
+    pub fn display_name(&self) -> &str {
+        match &self.name {
+            Some(name) => name,
+            None => &self.email,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) if name.contains(|c: char| ",;<>@\"".contains(c)) => {
+                write!(f, "\"{}\" <{}>", name.replace('"', "\\\""), self.email)
+            }
+            Some(name) => write!(f, "{} <{}>", name, self.email),
+            None => write!(f, "{}", self.email),
+        }
+    }
+}
+
+/// Parses an address list header (From, To, Cc, ...) into its mailboxes. Groups are
+/// flattened into their members. If the header can't be parsed as an RFC 5322 address
+/// list, its decoded value is kept whole so nothing silently disappears.
+pub fn parse_header(header: &MailHeader) -> Vector<Address> {
+    match addrparse_header(header) {
+        Ok(list) => list
+            .iter()
+            .flat_map(|addr| match addr {
+                MailAddr::Single(info) => vec![info.clone()],
+                MailAddr::Group(group) => group.addrs.clone(),
+            })
+            .map(|info| Address::new(info.display_name, info.addr))
+            .collect(),
+        Err(_) => {
+            let value = header.get_value();
+            if value.trim().is_empty() {
+                Vector::new()
+            } else {
+                Vector::unit(Address::new(None, value.trim()))
+            }
+        }
+    }
+}
+
+/// Parses an address list from a bare header value, such as one read back from notmuch.
+pub fn parse_value(value: &str) -> Vector<Address> {
+    match mailparse::parse_header(format!("X: {}", value).as_bytes()) {
+        Ok((header, _)) => parse_header(&header),
+        Err(_) => Vector::new(),
+    }
+}
+
+/// Collects the mailboxes from every instance of `key` in `headers`.
+pub fn parse_all(headers: &[MailHeader], key: &str) -> Vector<Address> {
+    headers
+        .get_all_headers(key)
+        .into_iter()
+        .flat_map(parse_header)
+        .collect()
+}
+
+/// Formats a list of addresses for display, using names where available.
+pub fn display_list(addresses: &Vector<Address>) -> String {
+    addresses
+        .iter()
+        .map(|address| address.display_name())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// The longest an encoded word may be, including its `=?charset?Q?` and `?=`
+const MAX_ENCODED_WORD: usize = 75;
+
+/// Encodes a header value as RFC 2047 Q-encoded words if it isn't plain ASCII. Long
+/// values are split into several words, each folded onto a line of its own, without
+/// splitting a character's bytes between words.
+pub fn encode_words(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    const PREFIX: &str = "=?UTF-8?Q?";
+    const SUFFIX: &str = "?=";
+    let room = MAX_ENCODED_WORD - PREFIX.len() - SUFFIX.len();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in value.chars() {
+        let mut bytes = [0; 4];
+        let mut encoded = String::new();
+        for byte in c.encode_utf8(&mut bytes).bytes() {
+            match byte {
+                b' ' => encoded.push('_'),
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'!' | b'*' | b'/' => {
+                    encoded.push(byte as char)
+                }
+                _ => encoded.push_str(&format!("={:02X}", byte)),
+            }
+        }
+        if word.len() + encoded.len() > room {
+            words.push(format!("{}{}{}", PREFIX, word, SUFFIX));
+            word.clear();
+        }
+        word.push_str(&encoded);
+    }
+    words.push(format!("{}{}{}", PREFIX, word, SUFFIX));
+    words.join("\n ")
+}
+
+/// Decodes any RFC 2047 encoded words in a bare string, such as the author names notmuch
+/// hands back for a thread.
+pub fn decode_words(text: &str) -> String {
+    if !text.contains("=?") {
+        return text.to_string();
+    }
+    match mailparse::parse_header(format!("X: {}", text).as_bytes()) {
+        Ok((header, _)) => header.get_value(),
+        Err(_) => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_is_left_alone() {
+        assert_eq!(encode_words("Lunch on Friday?"), "Lunch on Friday?");
+    }
+
+    #[test]
+    fn long_values_are_split_into_short_words() {
+        let subject = "Über die Straße gehen wir morgen früh um neun, oder lieber später?";
+        let encoded = encode_words(subject);
+        let words: Vec<&str> = encoded.split("\n ").collect();
+        assert!(words.len() > 1);
+        for word in &words {
+            assert!(word.starts_with("=?UTF-8?Q?") && word.ends_with("?="));
+            assert!(word.len() <= MAX_ENCODED_WORD, "{} is too long", word);
+        }
+        assert_eq!(decode_words(&encoded), subject);
+    }
+
+    #[test]
+    fn characters_are_not_split_between_words() {
+        let encoded = encode_words(&"é".repeat(40));
+        for word in encoded.split("\n ") {
+            let payload = &word["=?UTF-8?Q?".len()..word.len() - "?=".len()];
+            assert_eq!(payload.len() % "=C3=A9".len(), 0);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::mail::Email;
+
+/// How many parsed messages to keep around between thread loads.
+const CACHE_CAPACITY: usize = 500;
+
+struct Entry {
+    modified: SystemTime,
+    email: Email,
+    last_used: u64,
+}
+
+/// A least-recently-used cache of parsed messages, keyed by path and invalidated when a
+/// file's modification time changes (e.g. when another client rewrites its flags).
+#[derive(Default)]
+pub struct MessageCache {
+    entries: HashMap<PathBuf, Entry>,
+    clock: u64,
+}
+
+impl MessageCache {
+    pub fn get(&mut self, path: &Path, modified: SystemTime) -> Option<Email> {
+        self.clock += 1;
+        let clock = self.clock;
+        match self.entries.get_mut(path) {
+            Some(entry) if entry.modified == modified => {
+                entry.last_used = clock;
+                Some(entry.email.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, path: PathBuf, modified: SystemTime, email: Email) {
+        self.clock += 1;
+        if self.entries.len() >= CACHE_CAPACITY && !self.entries.contains_key(&path) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            path,
+            Entry {
+                modified,
+                email,
+                last_used: self.clock,
+            },
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use chrono::{DateTime, Duration, Local};
+use druid::im::Vector;
+use druid::{Data, Lens};
+
+use crate::address;
+use crate::db::DbHandle;
+use crate::identity::{self, Identity};
+use crate::mail::Email;
+use crate::store::Write;
+use crate::{followup, maildir, outbox, transport};
+
+/// The state of a message being written in a compose window.
+#[derive(Clone, Data, Lens)]
+pub struct Draft {
+    /// Index into `MailData::identities` of the identity to send as
+    pub identity: usize,
+    pub to: String,
+    pub cc: String,
+    pub bcc: String,
+    pub subject: String,
+    pub body: String,
+    pub in_reply_to: String,
+    pub references: String,
+    /// Feedback shown at the bottom of the compose window, such as a send failure
+    pub status: String,
+    /// Where the draft was last autosaved, if it has been
+    pub path: Option<Arc<PathBuf>>,
+    /// Bring the message back to the inbox if nobody replies within this many days
+    pub follow_up: Option<u32>,
+    /// The time typed in to send the message later
+    pub send_at: String,
+    /// Whether the list of identities to send from is open
+    pub choosing_identity: bool,
+}
+
+fn with_signature(body: String, identity: Option<&Identity>) -> String {
+    match identity {
+        Some(identity) if !identity.signature.is_empty() => {
+            format!("{}\n\n-- \n{}", body, identity.signature)
+        }
+        _ => body,
+    }
+}
+
+impl Draft {
+    pub fn new(identities: &Vector<Identity>) -> Self {
+        let identity = identity::default_index(identities);
+        Self {
+            identity,
+            to: String::new(),
+            cc: String::new(),
+            bcc: String::new(),
+            subject: String::new(),
+            body: with_signature(String::new(), identities.get(identity)),
+            in_reply_to: String::new(),
+            references: String::new(),
+            status: String::new(),
+            path: None,
+            follow_up: None,
+            send_at: String::new(),
+            choosing_identity: false,
+        }
+    }
+
+    /// A reply to `email`, sent from whichever identity it was addressed to.
+    pub fn reply(email: &Email, identities: &Vector<Identity>) -> Self {
+        let identity = identity::for_reply(identities, email);
+        let recipients = if email.reply_to.is_empty() {
+            &email.from
+        } else {
+            &email.reply_to
+        };
+        let subject = if email.subject.to_lowercase().starts_with("re:") {
+            email.subject.clone()
+        } else {
+            format!("Re: {}", email.subject)
+        };
+        let message_id = email.header("Message-ID").unwrap_or_default().to_string();
+        let references = match email.header("References") {
+            Some(references) => format!("{} {}", references, message_id),
+            None => message_id.clone(),
+        };
+        let quoted = email
+            .body
+            .lines()
+            .map(|line| format!("> {}", line))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let attribution = format!(
+            "On {}, {} wrote:",
+            email.date.format("%a, %b %e, %Y at %H:%M"),
+            address::display_list(&email.from)
+        );
+
+        Self {
+            identity,
+            to: recipients
+                .iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+            cc: String::new(),
+            bcc: String::new(),
+            subject,
+            body: with_signature(
+                format!("\n\n{}\n{}", attribution, quoted),
+                identities.get(identity),
+            ),
+            in_reply_to: message_id,
+            references: references.trim().to_string(),
+            status: String::new(),
+            path: None,
+            follow_up: None,
+            send_at: String::new(),
+            choosing_identity: false,
+        }
+    }
+
+    /// Restores a draft that was saved to the drafts maildir, so that editing it picks
+    /// up where it was left off.
+    pub fn from_saved(email: &Email, identities: &Vector<Identity>) -> Self {
+        let join = |addresses: &Vector<address::Address>| {
+            addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let identity = email
+            .from
+            .iter()
+            .find_map(|from| {
+                identities
+                    .iter()
+                    .position(|identity| identity.address.eq_ignore_ascii_case(&from.email))
+            })
+            .unwrap_or_else(|| identity::default_index(identities));
+
+        Self {
+            identity,
+            to: join(&email.to),
+            cc: join(&email.cc),
+            bcc: join(&email.bcc),
+            subject: email.subject.clone(),
+            body: email.body.clone(),
+            in_reply_to: email.header("In-Reply-To").unwrap_or_default().to_string(),
+            references: email.header("References").unwrap_or_default().to_string(),
+            status: String::new(),
+            path: Some(email.path.clone()),
+            follow_up: None,
+            send_at: String::new(),
+            choosing_identity: false,
+        }
+    }
+
+    /// Whether the two drafts would make the same message. Status updates and the
+    /// saved path don't count as edits.
+    pub fn same_message(&self, other: &Draft) -> bool {
+        self.identity == other.identity
+            && self.to == other.to
+            && self.cc == other.cc
+            && self.bcc == other.bcc
+            && self.subject == other.subject
+            && self.body == other.body
+            && self.follow_up == other.follow_up
+    }
+
+    /// Renders the draft as a plain text RFC 5322 message.
+    pub fn to_rfc5322(&self, identity: &Identity, message_id: &str) -> String {
+        let mut headers = vec![("From", identity.mailbox())];
+        if !self.to.trim().is_empty() {
+            headers.push(("To", self.to.trim().trim_end_matches(',').to_string()));
+        }
+        if !self.cc.trim().is_empty() {
+            headers.push(("Cc", self.cc.trim().trim_end_matches(',').to_string()));
+        }
+        if !self.bcc.trim().is_empty() {
+            headers.push(("Bcc", self.bcc.trim().trim_end_matches(',').to_string()));
+        }
+        headers.push(("Subject", address::encode_words(&self.subject)));
+        headers.push(("Date", Local::now().to_rfc2822()));
+        headers.push(("Message-ID", format!("<{}>", message_id)));
+        if !self.in_reply_to.is_empty() {
+            headers.push(("In-Reply-To", self.in_reply_to.clone()));
+            headers.push(("References", self.references.clone()));
+        }
+        headers.push(("MIME-Version", "1.0".to_string()));
+        headers.push(("Content-Type", "text/plain; charset=utf-8".to_string()));
+        headers.push(("Content-Transfer-Encoding", "8bit".to_string()));
+        headers.push(("User-Agent", "Seneca".to_string()));
+
+        let mut message = String::new();
+        for (key, value) in headers {
+            message.push_str(&format!("{}: {}\n", key, value));
+        }
+        message.push('\n');
+        message.push_str(&self.body);
+        if !self.body.ends_with('\n') {
+            message.push('\n');
+        }
+        message
+    }
+}
+
+/// A new Message-ID for mail from `identity`, without the angle brackets.
+pub fn new_message_id(identity: &Identity) -> String {
+    let domain = identity.address.rsplit('@').next().unwrap_or("localhost");
+    format!("{}@{}", maildir::unique_name(), domain)
+}
+
+/// Writes a copy of a sent message into the identity's sent maildir and indexes it with
+/// the identity's sent tags.
+pub fn save_sent_copy(identity: &Identity, message: &[u8], db: &DbHandle) -> Result<(), String> {
+    let folder = match &identity.sent_folder {
+        Some(folder) => folder,
+        None => return Ok(()),
+    };
+    let path = maildir::deliver(Path::new(folder), "S", message)
+        .map_err(|e| format!("Unable to save sent copy to {}: {}", folder, e))?;
+    db.write_and_wait(Write::Index {
+        path,
+        tags: identity.sent_tags.iter().cloned().collect(),
+    })
+}
+
+type DraftJob = Box<dyn FnOnce(&mut Option<PathBuf>) + Send>;
+
+/// Does the saving, sending and discarding for one compose window on a thread of its
+/// own, one job at a time, so an autosave can't race with sending the same draft. Each
+/// job is given where the draft is currently saved, and updates it. The thread ends
+/// once the worker is dropped and its last job is done.
+pub struct DraftWorker {
+    jobs: Sender<DraftJob>,
+}
+
+impl DraftWorker {
+    pub fn spawn(saved: Option<PathBuf>) -> Self {
+        let (jobs, receiver) = mpsc::channel::<DraftJob>();
+        thread::spawn(move || {
+            let mut saved = saved;
+            for job in receiver {
+                job(&mut saved);
+            }
+        });
+        Self { jobs }
+    }
+
+    pub fn run(&self, job: impl FnOnce(&mut Option<PathBuf>) + Send + 'static) {
+        let _ = self.jobs.send(Box::new(job));
+    }
+}
+
+/// Writes the draft into the drafts maildir and indexes it tagged `draft`, replacing the
+/// copy at `saved`, which is updated to the new copy.
+pub fn save_draft(
+    draft: &Draft,
+    identity: &Identity,
+    folder: &Path,
+    saved: &mut Option<PathBuf>,
+    db: &DbHandle,
+) -> Result<PathBuf, String> {
+    let message = draft.to_rfc5322(identity, &new_message_id(identity));
+    let path = maildir::deliver(folder, "DS", message.as_bytes())
+        .map_err(|e| format!("Unable to save draft to {}: {}", folder.display(), e))?;
+    db.write_and_wait(Write::Index {
+        path: path.clone(),
+        tags: vec!["draft".to_string()],
+    })?;
+    if let Some(old) = saved.replace(path.clone()) {
+        delete_draft(&old, db)?;
+    }
+    Ok(path)
+}
+
+/// Deletes a saved draft from disk and from the database.
+pub fn delete_draft(path: &Path, db: &DbHandle) -> Result<(), String> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(format!("Unable to delete {}: {}", path.display(), e));
+        }
+        _ => {}
+    }
+    db.write_and_wait(Write::Remove {
+        path: path.to_path_buf(),
+    })
+}
+
+/// Follow-up reminders are kept on the sent copy, so there has to be one.
+fn check_follow_up(draft: &Draft, identity: &Identity) -> Result<(), String> {
+    if draft.follow_up.is_some() && identity.sent_folder.is_none() {
+        return Err(format!(
+            "Follow-ups need a sent-folder for {} in config.toml.",
+            identity.address
+        ));
+    }
+    Ok(())
+}
+
+/// Sends a draft from `identity` straight away and removes the saved copy, if any.
+pub fn send(
+    draft: &Draft,
+    identity: &Identity,
+    saved: &mut Option<PathBuf>,
+    db: &DbHandle,
+) -> Result<(), String> {
+    check_follow_up(draft, identity)?;
+    let message_id = new_message_id(identity);
+    let message = draft.to_rfc5322(identity, &message_id);
+    transport::send(identity, message.as_bytes())?;
+    file_sent(
+        identity,
+        message.as_bytes(),
+        &message_id,
+        draft.follow_up,
+        db,
+    )?;
+    match saved.take() {
+        Some(path) => delete_draft(&path, db),
+        None => Ok(()),
+    }
+}
+
+/// Puts a draft from `identity` in the outbox at `folder` to be sent at `send_at`, and
+/// removes the saved copy, if any.
+pub fn queue(
+    draft: &Draft,
+    identity: &Identity,
+    folder: &Path,
+    send_at: &DateTime<Local>,
+    saved: &mut Option<PathBuf>,
+    db: &DbHandle,
+) -> Result<(), String> {
+    check_follow_up(draft, identity)?;
+    let message = draft.to_rfc5322(identity, &new_message_id(identity));
+    outbox::queue(folder, &message, send_at, draft.follow_up, db)?;
+    match saved.take() {
+        Some(path) => delete_draft(&path, db),
+        None => Ok(()),
+    }
+}
+
+/// Files the sent copy of a message that has gone out, and asks for a follow-up on it
+/// after `follow_up` days without a reply.
+pub fn file_sent(
+    identity: &Identity,
+    message: &[u8],
+    message_id: &str,
+    follow_up: Option<u32>,
+    db: &DbHandle,
+) -> Result<(), String> {
+    save_sent_copy(identity, message, db)?;
+    if let Some(days) = follow_up {
+        let by = Local::now() + Duration::days(days.into());
+        for write in followup::request(message_id, &by) {
+            db.write(write);
+        }
+    }
+    Ok(())
+}
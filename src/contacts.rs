@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::RwLock;
+use std::thread;
+
+use chrono::Local;
+use druid::Data;
+use lazy_static::lazy_static;
+
+use crate::address::{self, Address};
+use crate::store::{MailStore, StoreError};
+
+lazy_static! {
+    /// The address book shared by every autocompleting text field.
+    pub static ref ADDRESS_BOOK: RwLock<AddressBook> = RwLock::new(AddressBook::default());
+}
+
+#[derive(Clone, Data, Debug)]
+pub struct Contact {
+    pub address: Address,
+    /// How many message headers this address has appeared in
+    pub count: u32,
+    /// Unix timestamp of the newest message this address appeared in
+    pub last_seen: i64,
+}
+
+impl Contact {
+    /// Frequency weighted by recency, so that someone we wrote to every day last year
+    /// doesn't outrank someone we've been talking to all week.
+    fn score(&self, now: i64) -> f64 {
+        let age_days = ((now - self.last_seen).max(0) / 86_400) as f64;
+        (1.0 + self.count as f64) / (1.0 + age_days / 30.0)
+    }
+}
+
+/// Correspondents seen in the From/To/Cc headers of the notmuch database, keyed by
+/// lowercased address.
+#[derive(Default)]
+pub struct AddressBook {
+    contacts: HashMap<String, Contact>,
+    /// The database revision the last scan got up to, so later scans only look at
+    /// messages that have been added or changed since.
+    revision: Option<u64>,
+    /// The date of the newest message counted so far. A later scan also finds old
+    /// messages that were only retagged, and those mustn't be counted twice.
+    newest: i64,
+}
+
+impl AddressBook {
+    fn add(&mut self, address: Address, date: i64) {
+        let contact = self
+            .contacts
+            .entry(address.email.to_lowercase())
+            .or_insert_with(|| Contact {
+                address: address.clone(),
+                count: 0,
+                last_seen: date,
+            });
+        contact.count += 1;
+        if date >= contact.last_seen {
+            contact.last_seen = date;
+            // Prefer the most recent display name, but don't throw a name away for none
+            if address.name.is_some() {
+                contact.address.name = address.name;
+            }
+        }
+    }
+
+    /// Returns up to `limit` contacts whose name or address contains a word starting
+    /// with `prefix`, best matches first.
+    pub fn complete(&self, prefix: &str, limit: usize) -> Vec<Contact> {
+        let prefix = prefix.trim().to_lowercase();
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        let now = Local::now().timestamp();
+        let mut matches = self
+            .contacts
+            .values()
+            .filter(|contact| {
+                let email = contact.address.email.to_lowercase();
+                let name = contact
+                    .address
+                    .name
+                    .clone()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                email.starts_with(&prefix)
+                    || name.starts_with(&prefix)
+                    || name
+                        .split_whitespace()
+                        .any(|word| word.starts_with(&prefix))
+                    || email
+                        .split(|c: char| c == '.' || c == '@' || c == '_' || c == '-')
+                        .any(|word| word.starts_with(&prefix))
+            })
+            .cloned()
+            .collect::<Vec<Contact>>();
+        matches.sort_by(|a, b| {
+            b.score(now)
+                .partial_cmp(&a.score(now))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        matches.truncate(limit);
+        matches
+    }
+
+    /// Adds the contacts from a vCard file. Imported entries carry no message history,
+    /// so anyone we've actually corresponded with still ranks above them.
+    pub fn import_vcard(&mut self, path: &Path) -> std::io::Result<usize> {
+        let contents = std::fs::read_to_string(path)?;
+        // Undo RFC 6350 line folding before looking at properties
+        let unfolded = contents
+            .replace("\r\n", "\n")
+            .replace("\n ", "")
+            .replace("\n\t", "");
+
+        let mut imported = 0;
+        let mut name: Option<String> = None;
+        let mut emails = Vec::new();
+        for line in unfolded.lines() {
+            let (property, value) = match line.split_once(':') {
+                Some(split) => split,
+                None => continue,
+            };
+            let property = property
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .to_uppercase();
+            match property.as_str() {
+                "BEGIN" => {
+                    name = None;
+                    emails.clear();
+                }
+                "FN" => name = Some(value.trim().to_string()),
+                "EMAIL" => emails.push(value.trim().to_string()),
+                "END" => {
+                    for email in emails.drain(..) {
+                        self.contacts
+                            .entry(email.to_lowercase())
+                            .or_insert_with(|| Contact {
+                                address: Address::new(name.clone(), email),
+                                count: 0,
+                                last_seen: 0,
+                            });
+                        imported += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(imported)
+    }
+}
+
+/// Keeps the shared address book up to date on a thread of its own, with its own
+/// read-only store, so the first scan of the whole database doesn't hold up searches.
+#[derive(Clone)]
+pub struct ScannerHandle {
+    requests: Sender<()>,
+}
+
+impl ScannerHandle {
+    /// Starts the scanner. The store is created on the scanner's thread, since notmuch
+    /// databases can't be moved between threads.
+    pub fn spawn<F>(open_store: F) -> Self
+    where
+        F: FnOnce() -> Box<dyn MailStore> + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let mut store = open_store();
+            while receiver.recv().is_ok() {
+                // One scan covers every request that came in while the last one ran
+                while receiver.try_recv().is_ok() {}
+                store.refresh();
+                if let Err(e) = update_address_book(&mut *store) {
+                    eprintln!("Unable to update the address book: {}", e);
+                }
+            }
+        });
+        Self { requests }
+    }
+
+    /// Reads the addresses from any messages added since the last scan.
+    pub fn update(&self) {
+        let _ = self.requests.send(());
+    }
+}
+
+/// Brings the shared address book up to date with the database. Each call reads the
+/// messages modified in the revisions since the previous one, up to the current
+/// revision, so no two scans look at the same changes. Of those, only messages newer
+/// than any counted before are new; the rest were retagged.
+fn update_address_book(store: &mut dyn MailStore) -> Result<(), StoreError> {
+    let (since, newest) = {
+        let book = ADDRESS_BOOK.read().unwrap();
+        (book.revision, book.newest)
+    };
+    let revision = store.revision()?;
+    if since == Some(revision) {
+        return Ok(());
+    }
+
+    let first = since.map_or(0, |since| since + 1);
+    let query = format!("lastmod:{}..{}", first, revision);
+    let mut seen = Vec::new();
+    for message in store.messages(&query)? {
+        if since.is_some() && message.date <= newest {
+            continue;
+        }
+        let mut addresses = Vec::new();
+        for value in &[&message.from, &message.to, &message.cc] {
+            addresses.extend(address::parse_value(value));
+        }
+        seen.push((message.date, addresses));
+    }
+
+    // Only hold the write lock for the merge so autocompletion never waits on the scan
+    let mut book = ADDRESS_BOOK.write().unwrap();
+    for (date, addresses) in seen {
+        book.newest = book.newest.max(date);
+        for address in addresses {
+            book.add(address, date);
+        }
+    }
+    book.revision = Some(revision);
+    Ok(())
+}
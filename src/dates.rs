@@ -0,0 +1,477 @@
+//! Dates as people read them: "5 minutes ago", "Yesterday" or "Tuesday" for recent mail
+//! and a date for anything older, in the user's language.
+
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Datelike, Duration, Locale, TimeZone, Timelike};
+use config::Config;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// The date format everything is shown with, set from `config.toml` at startup.
+    pub static ref DATE_FORMAT: RwLock<DateFormat> = RwLock::new(DateFormat::default());
+}
+
+/// How often relative dates need working out again to stay right.
+pub const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The words for relative dates in one language, along with the patterns for dates
+/// without the time. The names of months and days come from the locale.
+#[derive(Debug)]
+struct Words {
+    language: &'static str,
+    /// The locale to use when only the language is given
+    locale: &'static str,
+    just_now: &'static str,
+    /// "{}" stands for the number
+    minute_ago: &'static str,
+    minutes_ago: &'static str,
+    hour_ago: &'static str,
+    hours_ago: &'static str,
+    yesterday: &'static str,
+    /// The day and month, then the day, month and year
+    date: &'static str,
+    full: &'static str,
+}
+
+const LANGUAGES: &[Words] = &[
+    Words {
+        language: "en",
+        locale: "en_US",
+        just_now: "Just now",
+        minute_ago: "1 minute ago",
+        minutes_ago: "{} minutes ago",
+        hour_ago: "1 hour ago",
+        hours_ago: "{} hours ago",
+        yesterday: "Yesterday",
+        date: "%B %-d",
+        full: "%B %-d, %Y",
+    },
+    Words {
+        language: "de",
+        locale: "de_DE",
+        just_now: "Gerade eben",
+        minute_ago: "vor 1 Minute",
+        minutes_ago: "vor {} Minuten",
+        hour_ago: "vor 1 Stunde",
+        hours_ago: "vor {} Stunden",
+        yesterday: "Gestern",
+        date: "%-d. %B",
+        full: "%-d. %B %Y",
+    },
+    Words {
+        language: "fr",
+        locale: "fr_FR",
+        just_now: "À l'instant",
+        minute_ago: "il y a 1 minute",
+        minutes_ago: "il y a {} minutes",
+        hour_ago: "il y a 1 heure",
+        hours_ago: "il y a {} heures",
+        yesterday: "Hier",
+        date: "%-d %B",
+        full: "%-d %B %Y",
+    },
+    Words {
+        language: "es",
+        locale: "es_ES",
+        just_now: "Ahora mismo",
+        minute_ago: "hace 1 minuto",
+        minutes_ago: "hace {} minutos",
+        hour_ago: "hace 1 hora",
+        hours_ago: "hace {} horas",
+        yesterday: "Ayer",
+        date: "%-d de %B",
+        full: "%-d de %B de %Y",
+    },
+    Words {
+        language: "it",
+        locale: "it_IT",
+        just_now: "Proprio ora",
+        minute_ago: "1 minuto fa",
+        minutes_ago: "{} minuti fa",
+        hour_ago: "1 ora fa",
+        hours_ago: "{} ore fa",
+        yesterday: "Ieri",
+        date: "%-d %B",
+        full: "%-d %B %Y",
+    },
+    Words {
+        language: "nl",
+        locale: "nl_NL",
+        just_now: "Zojuist",
+        minute_ago: "1 minuut geleden",
+        minutes_ago: "{} minuten geleden",
+        hour_ago: "1 uur geleden",
+        hours_ago: "{} uur geleden",
+        yesterday: "Gisteren",
+        date: "%-d %B",
+        full: "%-d %B %Y",
+    },
+    Words {
+        language: "pt",
+        locale: "pt_PT",
+        just_now: "Agora mesmo",
+        minute_ago: "há 1 minuto",
+        minutes_ago: "há {} minutos",
+        hour_ago: "há 1 hora",
+        hours_ago: "há {} horas",
+        yesterday: "Ontem",
+        date: "%-d de %B",
+        full: "%-d de %B de %Y",
+    },
+];
+
+/// English speaking regions that put the month before the day.
+const MONTH_FIRST_REGIONS: &[&str] = &["US", "CA", "PH"];
+
+/// Regions whose clocks usually show AM and PM.
+const TWELVE_HOUR_REGIONS: &[&str] = &["US", "CA", "AU", "NZ", "PH", "IN", "PK", "EG"];
+
+/// The locale from the environment, as in "de_DE.UTF-8".
+fn environment_locale() -> Option<String> {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty() && value != "C" && value != "POSIX")
+}
+
+/// Splits a locale such as "pt_BR.UTF-8" into its language and region.
+fn split_locale(locale: &str) -> (String, String) {
+    let locale = locale.split(['.', '@']).next().unwrap_or("");
+    let mut parts = locale.split(['_', '-']);
+    let language = parts.next().unwrap_or("").to_lowercase();
+    let region = parts.next().unwrap_or("").to_uppercase();
+    (language, region)
+}
+
+/// The strftime patterns dates are shown with and the words that go in them.
+#[derive(Debug)]
+pub struct DateFormat {
+    words: &'static Words,
+    locale: Locale,
+    /// The locale has no words for AM and PM, so a 12 hour clock uses the English ones
+    english_am_pm: bool,
+    /// Mail from the last week, after yesterday
+    pub weekday: String,
+    /// Older mail from this year
+    pub date: String,
+    /// Mail from before this year
+    pub full: String,
+    /// Dates shown in full, as in the reading pane
+    pub long: String,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        Self::for_locale("en_US", None)
+    }
+}
+
+impl DateFormat {
+    /// The format for `locale`, with a 12 or 24 hour clock as given or as is usual there.
+    pub fn for_locale(locale: &str, twelve_hour: Option<bool>) -> Self {
+        let (language, region) = split_locale(locale);
+        let words = LANGUAGES
+            .iter()
+            .find(|words| words.language == language)
+            .unwrap_or(&LANGUAGES[0]);
+        // Regions the locale data doesn't know, or none, get the language's main one
+        let locale = Locale::try_from(format!("{}_{}", language, region).as_str())
+            .or_else(|_| Locale::try_from(words.locale))
+            .unwrap_or(Locale::en_US);
+        let noon = chrono::Utc.ymd(2000, 1, 1).and_hms(12, 0, 0);
+        let english_am_pm = noon.format_localized("%p", locale).to_string().is_empty();
+        let twelve_hour = twelve_hour.unwrap_or_else(|| {
+            TWELVE_HOUR_REGIONS.contains(&region.as_str())
+                || (language == "en" && region.is_empty())
+        });
+        let time = if twelve_hour { "%-I:%M %p" } else { "%H:%M" };
+        let (date, full) = if words.language == "en"
+            && !region.is_empty()
+            && !MONTH_FIRST_REGIONS.contains(&region.as_str())
+        {
+            ("%-d %B", "%-d %B %Y")
+        } else {
+            (words.date, words.full)
+        };
+        Self {
+            words,
+            locale,
+            english_am_pm,
+            weekday: "%A".to_string(),
+            date: date.to_string(),
+            full: full.to_string(),
+            long: format!("%a {}, {}", full, time),
+        }
+    }
+
+    /// Reads the `[dates]` table of `config.toml`, going by the environment's locale for
+    /// anything it leaves out.
+    pub fn from_config(config: &Config) -> Self {
+        let locale = config
+            .get_string("dates.locale")
+            .ok()
+            .or_else(environment_locale)
+            .unwrap_or_else(|| "en_US".to_string());
+        let twelve_hour = match config.get_string("dates.clock").as_deref() {
+            Ok("12h") => Some(true),
+            Ok("24h") => Some(false),
+            Ok(other) => {
+                eprintln!("Ignoring dates.clock = {:?}: use \"12h\" or \"24h\"", other);
+                None
+            }
+            Err(_) => None,
+        };
+        let mut format = Self::for_locale(&locale, twelve_hour);
+        for (key, pattern) in [
+            ("dates.weekday", &mut format.weekday),
+            ("dates.date", &mut format.date),
+            ("dates.full", &mut format.full),
+            ("dates.long", &mut format.long),
+        ] {
+            if let Ok(value) = config.get_string(key) {
+                *pattern = value;
+            }
+        }
+        format
+    }
+
+    /// Formats `date` with `pattern`, with the names of months and days in this
+    /// format's language.
+    pub fn format<Tz: TimeZone>(&self, date: &DateTime<Tz>, pattern: &str) -> String
+    where
+        Tz::Offset: Display,
+    {
+        if !self.english_am_pm {
+            return date.format_localized(pattern, self.locale).to_string();
+        }
+        let am_pm = if date.hour() < 12 { "AM" } else { "PM" };
+        let mut pattern = pattern.to_string();
+        let mut from = 0;
+        while let Some(at) = pattern[from..].find('%').map(|at| from + at) {
+            match pattern[at + 1..].chars().next() {
+                Some('p') => {
+                    pattern.replace_range(at..at + 2, am_pm);
+                    from = at + am_pm.len();
+                }
+                Some(other) => from = at + 1 + other.len_utf8(),
+                None => break,
+            }
+        }
+        date.format_localized(&pattern, self.locale).to_string()
+    }
+
+    /// `date` in full.
+    pub fn long<Tz: TimeZone>(&self, date: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        self.format(date, &self.long)
+    }
+
+    /// `date` as seen at `now`: how long ago for the last few hours, "Yesterday" or the day
+    /// of the week for the last week, and the date for anything older or in the future.
+    pub fn relative<Tz: TimeZone>(&self, date: &DateTime<Tz>, now: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        let elapsed = now.clone().signed_duration_since(date.clone());
+        let days = now
+            .naive_local()
+            .date()
+            .signed_duration_since(date.naive_local().date())
+            .num_days();
+        if elapsed < Duration::zero() || days < 0 {
+            if days == 0 {
+                self.words.just_now.to_string()
+            } else {
+                self.format(date, &self.full)
+            }
+        } else if elapsed < Duration::minutes(1) {
+            self.words.just_now.to_string()
+        } else if elapsed < Duration::hours(1) {
+            match elapsed.num_minutes() {
+                1 => self.words.minute_ago.to_string(),
+                minutes => self.words.minutes_ago.replace("{}", &minutes.to_string()),
+            }
+        } else if days == 0 {
+            match elapsed.num_hours() {
+                1 => self.words.hour_ago.to_string(),
+                hours => self.words.hours_ago.replace("{}", &hours.to_string()),
+            }
+        } else if days == 1 {
+            self.words.yesterday.to_string()
+        } else if days < 7 {
+            self.format(date, &self.weekday)
+        } else if date.year() == now.year() {
+            self.format(date, &self.date)
+        } else {
+            self.format(date, &self.full)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn at(date: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(date).unwrap()
+    }
+
+    /// A Wednesday afternoon.
+    fn now() -> DateTime<FixedOffset> {
+        at("2021-06-16T15:30:00+02:00")
+    }
+
+    #[test]
+    fn recent_mail_is_minutes_or_hours_ago() {
+        let format = DateFormat::for_locale("en_GB", None);
+        assert_eq!(
+            format.relative(&at("2021-06-16T15:29:40+02:00"), &now()),
+            "Just now"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-16T15:29:00+02:00"), &now()),
+            "1 minute ago"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-16T14:45:00+02:00"), &now()),
+            "45 minutes ago"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-16T14:30:00+02:00"), &now()),
+            "1 hour ago"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-16T00:10:00+02:00"), &now()),
+            "15 hours ago"
+        );
+    }
+
+    #[test]
+    fn older_mail_is_a_day_or_a_date() {
+        let format = DateFormat::for_locale("en_GB", None);
+        assert_eq!(
+            format.relative(&at("2021-06-15T23:50:00+02:00"), &now()),
+            "Yesterday"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-14T09:00:00+02:00"), &now()),
+            "Monday"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-10T09:00:00+02:00"), &now()),
+            "Thursday"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-09T09:00:00+02:00"), &now()),
+            "9 June"
+        );
+        assert_eq!(
+            format.relative(&at("2020-12-31T09:00:00+02:00"), &now()),
+            "31 December 2020"
+        );
+    }
+
+    #[test]
+    fn just_after_midnight_counts_minutes_not_days() {
+        let format = DateFormat::for_locale("en_GB", None);
+        let now = at("2021-06-16T00:05:00+02:00");
+        assert_eq!(
+            format.relative(&at("2021-06-15T23:55:00+02:00"), &now),
+            "10 minutes ago"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-15T22:00:00+02:00"), &now),
+            "Yesterday"
+        );
+    }
+
+    #[test]
+    fn mail_from_the_future_gets_a_date() {
+        let format = DateFormat::for_locale("en_GB", None);
+        assert_eq!(
+            format.relative(&at("2021-06-16T15:31:00+02:00"), &now()),
+            "Just now"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-18T09:00:00+02:00"), &now()),
+            "18 June 2021"
+        );
+    }
+
+    #[test]
+    fn us_english_puts_the_month_first_and_uses_am_and_pm() {
+        let format = DateFormat::for_locale("en_US.UTF-8", None);
+        assert_eq!(
+            format.relative(&at("2021-03-05T09:00:00+02:00"), &now()),
+            "March 5"
+        );
+        assert_eq!(
+            format.relative(&at("2020-03-05T09:00:00+02:00"), &now()),
+            "March 5, 2020"
+        );
+        assert_eq!(
+            format.long(&at("2021-03-05T21:07:00+02:00")),
+            "Fri March 5, 2021, 9:07 PM"
+        );
+    }
+
+    #[test]
+    fn other_languages_use_their_own_words() {
+        let format = DateFormat::for_locale("de_DE.UTF-8", None);
+        assert_eq!(
+            format.relative(&at("2021-03-05T09:00:00+02:00"), &now()),
+            "5. März"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-14T09:00:00+02:00"), &now()),
+            "Montag"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-15T09:00:00+02:00"), &now()),
+            "Gestern"
+        );
+        assert_eq!(
+            format.relative(&at("2021-06-16T12:00:00+02:00"), &now()),
+            "vor 3 Stunden"
+        );
+        assert_eq!(
+            format.long(&at("2021-03-05T21:07:00+02:00")),
+            "Fr 5. März 2021, 21:07"
+        );
+    }
+
+    #[test]
+    fn the_clock_can_be_chosen() {
+        let format = DateFormat::for_locale("fr_FR", Some(true));
+        assert_eq!(
+            format.long(&at("2021-03-05T21:07:00+02:00")),
+            "ven. 5 mars 2021, 9:07 PM"
+        );
+        let format = DateFormat::for_locale("en_US", Some(false));
+        assert_eq!(
+            format.long(&at("2021-03-05T21:07:00+02:00")),
+            "Fri March 5, 2021, 21:07"
+        );
+    }
+
+    #[test]
+    fn patterns_keep_everything_but_names() {
+        let format = DateFormat::for_locale("fr", None);
+        assert_eq!(format.format(&now(), "%d/%m %b 100%%"), "16/06 juin 100%");
+    }
+
+    #[test]
+    fn unknown_languages_fall_back_to_english() {
+        let format = DateFormat::for_locale("xx_YY", None);
+        assert_eq!(
+            format.relative(&at("2021-06-15T09:00:00+02:00"), &now()),
+            "Yesterday"
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use druid::{ArcStr, ExtEventSink};
+
+use crate::contacts::ScannerHandle;
+use crate::mail::MessageSet;
+use crate::store::{MailStore, MessageSource, StoreError, Write};
+use crate::{followup, mail, snooze, MailData};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_WRITE_ATTEMPTS: u32 = 10;
+/// The longest the service sleeps before looking at the clock again, so a wake time
+/// isn't missed for long when the computer was suspended or its clock changed.
+const MAX_WAKE_WAIT: Duration = Duration::from_secs(60);
+
+enum Request {
+    Search(ArcStr),
+    ShowExcluded(bool),
+    CountUnread(Vec<ArcStr>),
+    Write(Write, Option<Sender<Result<(), String>>>),
+}
+
+/// A handle to the database service thread. The service owns the `MailStore` and
+/// applies queued changes in batches, so Seneca only holds the write lock briefly and
+/// doesn't fight `notmuch new` for it.
+#[derive(Clone)]
+pub struct DbHandle {
+    requests: Sender<Request>,
+    source: Arc<dyn MessageSource>,
+}
+
+impl DbHandle {
+    /// Starts the service. The store is created on the service thread, since notmuch
+    /// databases can't be moved between threads. `own_addresses` are those of the
+    /// user's identities, whose messages don't count as replies to a reminder.
+    pub fn spawn<F>(
+        open_store: F,
+        own_addresses: Vec<String>,
+        contacts: ScannerHandle,
+        event_sink: ExtEventSink,
+    ) -> Self
+    where
+        F: FnOnce() -> Box<dyn MailStore> + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::channel();
+        let (sources, source) = mpsc::channel();
+        thread::spawn(move || {
+            let store = open_store();
+            let _ = sources.send(store.source());
+            Service {
+                store,
+                own_addresses,
+                contacts,
+                event_sink,
+                pending: VecDeque::new(),
+                attempts: 0,
+                retry_at: None,
+                muted_revision: None,
+                query: None,
+                counted: Vec::new(),
+                wake_at: None,
+                check_schedule: true,
+            }
+            .run(receiver)
+        });
+        let source = source
+            .recv()
+            .expect("The database service stopped while starting");
+        Self { requests, source }
+    }
+
+    /// Where to read the messages the store's paths point at.
+    pub fn source(&self) -> Arc<dyn MessageSource> {
+        self.source.clone()
+    }
+
+    /// Runs `query` and replaces the thread list with the results.
+    pub fn search(&self, query: ArcStr) {
+        let _ = self.requests.send(Request::Search(query));
+    }
+
+    /// Whether later searches include messages with an excluded tag.
+    pub fn show_excluded(&self, show: bool) {
+        let _ = self.requests.send(Request::ShowExcluded(show));
+    }
+
+    /// Keeps the unread counts of the searches `queries` up to date in the sidebar.
+    pub fn count_unread(&self, queries: Vec<ArcStr>) {
+        let _ = self.requests.send(Request::CountUnread(queries));
+    }
+
+    /// Queues a change. Failures are reported in the UI's status line.
+    pub fn write(&self, write: Write) {
+        let _ = self.requests.send(Request::Write(write, None));
+    }
+
+    /// Queues a change and blocks until it has been applied or given up on. Must not be
+    /// called from the UI thread.
+    pub fn write_and_wait(&self, write: Write) -> Result<(), String> {
+        let (done, result) = mpsc::channel();
+        self.requests
+            .send(Request::Write(write, Some(done)))
+            .map_err(|_| "The database service has stopped".to_string())?;
+        result
+            .recv()
+            .map_err(|_| "The database service has stopped".to_string())?
+    }
+}
+
+struct Service {
+    store: Box<dyn MailStore>,
+    own_addresses: Vec<String>,
+    contacts: ScannerHandle,
+    event_sink: ExtEventSink,
+    pending: VecDeque<(Write, Option<Sender<Result<(), String>>>)>,
+    attempts: u32,
+    retry_at: Option<Instant>,
+    /// The database revision muted threads were last checked for new mail up to
+    muted_revision: Option<u64>,
+    /// The last search, to run again when threads come back to the inbox
+    query: Option<ArcStr>,
+    /// The searches whose unread messages are counted
+    counted: Vec<ArcStr>,
+    /// When the next snoozed thread or follow-up reminder is due
+    wake_at: Option<DateTime<Local>>,
+    /// Set when wake times or reminders may have changed since they were looked at
+    check_schedule: bool,
+}
+
+impl Service {
+    fn run(mut self, requests: Receiver<Request>) {
+        loop {
+            let request = match self.timeout() {
+                Some(timeout) => match requests.recv_timeout(timeout) {
+                    Ok(request) => Some(request),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                },
+                None => match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => return,
+                },
+            };
+
+            match request {
+                Some(Request::Search(query)) => self.search(query),
+                Some(Request::ShowExcluded(show)) => self.store.set_show_excluded(show),
+                Some(Request::CountUnread(queries)) => {
+                    self.counted = queries;
+                    self.count_unread();
+                }
+                Some(Request::Write(write, done)) => self.pending.push_back((write, done)),
+                None => {}
+            }
+
+            let retry_due = self.retry_at.map_or(true, |at| at <= Instant::now());
+            if !self.pending.is_empty() && retry_due {
+                self.flush_writes();
+            }
+            if self.pending.is_empty() && self.schedule_due() && self.run_due() {
+                // Show threads that have come back if they match the current search
+                if let Some(query) = self.query.clone() {
+                    self.search(query);
+                }
+            }
+        }
+    }
+
+    /// Whether snoozed threads and reminders need looking at: something may have
+    /// changed them, or the next one is due.
+    fn schedule_due(&self) -> bool {
+        self.check_schedule || self.wake_at.map_or(false, |at| at <= Local::now())
+    }
+
+    /// How long to wait for a request before there's something else to do.
+    fn timeout(&self) -> Option<Duration> {
+        let retry = self
+            .retry_at
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        let wake = self.wake_at.map(|at| {
+            (at - Local::now())
+                .to_std()
+                .unwrap_or_default()
+                .min(MAX_WAKE_WAIT)
+        });
+        match (retry, wake) {
+            (Some(retry), Some(wake)) => Some(retry.min(wake)),
+            (retry, wake) => retry.or(wake),
+        }
+    }
+
+    fn report(&self, message: String) {
+        eprintln!("{}", message);
+        self.event_sink
+            .add_idle_callback(move |data: &mut MailData| data.status = message);
+    }
+
+    fn search(&mut self, query: ArcStr) {
+        self.query = Some(query.clone());
+        // Pick up any mail that has arrived since the last search
+        self.store.refresh();
+        if let Err(e) = self.archive_muted() {
+            self.report(format!("Unable to check muted threads for new mail: {}", e));
+        }
+        // Threads may have woken or reminders run out since the last look
+        if self.schedule_due() {
+            self.run_due();
+        }
+        let result = mail::load_mail(query, &self.event_sink, &mut *self.store);
+        // New mail may bring new correspondents
+        self.contacts.update();
+        if let Err(e) = result {
+            self.report(format!("Unable to search the mail database: {}", e));
+            self.event_sink
+                .add_idle_callback(|data: &mut MailData| data.done_loading = true);
+        }
+        self.count_unread();
+    }
+
+    /// Counts the unread messages found by each of the sidebar's searches, and shows the
+    /// counts next to them.
+    fn count_unread(&mut self) {
+        let mut counts = Vec::new();
+        for query in &self.counted {
+            match self.store.count_unread(query) {
+                Ok(count) => counts.push((query.clone(), count)),
+                Err(e) => {
+                    self.report(format!("Unable to count unread mail: {}", e));
+                    return;
+                }
+            }
+        }
+        self.event_sink
+            .add_idle_callback(move |data: &mut MailData| {
+                for (query, count) in counts {
+                    let searches = data.searches.search_list.iter_mut();
+                    for search in searches.filter(|search| search.1 == query) {
+                        search.2 = count;
+                    }
+                }
+            });
+    }
+
+    /// Tags every message that has arrived in a muted thread since the last check, or
+    /// since ever on the first one, `muted` like the rest of its thread, and takes
+    /// `inbox` off it. Messages that skip the inbox need the tag too, or they'd count as
+    /// unread wherever they land.
+    fn archive_muted(&mut self) -> Result<(), StoreError> {
+        let revision = self.store.revision()?;
+        if self.muted_revision == Some(revision) {
+            return Ok(());
+        }
+
+        let since = match self.muted_revision {
+            Some(since) => format!("lastmod:{}.. and ", since + 1),
+            None => String::new(),
+        };
+        let arrived = self.store.messages(&format!("{}not tag:muted", since))?;
+        if !arrived.is_empty() {
+            let muted: HashSet<String> = self
+                .store
+                .messages("tag:muted")?
+                .into_iter()
+                .map(|message| message.thread_id)
+                .collect();
+            let messages: Vec<String> = arrived
+                .into_iter()
+                .filter(|message| muted.contains(&message.thread_id))
+                .map(|message| message.id)
+                .collect();
+            if !messages.is_empty() {
+                let set = MessageSet {
+                    thread: String::new(),
+                    messages,
+                };
+                let write = Write::Tag {
+                    query: set.query(),
+                    add: vec!["muted".to_string()],
+                    remove: vec!["inbox".to_string()],
+                };
+                self.pending.push_back((write, None));
+                // Before the search, so the new messages are already out of the inbox
+                if self.retry_at.is_none() {
+                    self.flush_writes();
+                }
+            }
+        }
+        self.muted_revision = Some(revision);
+        Ok(())
+    }
+
+    /// Wakes the snoozed threads that are due and settles follow-up reminders that have
+    /// been answered or run out of time, and notes when the next is due. Returns whether
+    /// anything changed.
+    fn run_due(&mut self) -> bool {
+        type Check = fn(
+            &mut dyn MailStore,
+            &DateTime<Local>,
+            &[String],
+        ) -> Result<(Vec<Write>, Option<DateTime<Local>>), StoreError>;
+        let checks: [(&str, Check); 2] = [
+            ("snoozed threads", |store, now, _| {
+                snooze::wake_due(store, now)
+            }),
+            ("follow-ups", followup::due),
+        ];
+
+        self.store.refresh();
+        let now = Local::now();
+        let mut writes = Vec::new();
+        self.wake_at = None;
+        for (name, check) in checks.iter() {
+            match check(&mut *self.store, &now, &self.own_addresses) {
+                Ok((due, next)) => {
+                    writes.extend(due);
+                    self.wake_at = match (self.wake_at, next) {
+                        (Some(at), Some(next)) => Some(at.min(next)),
+                        (at, next) => at.or(next),
+                    };
+                }
+                Err(e) => self.report(format!("Unable to check {}: {}", name, e)),
+            }
+        }
+
+        let changed = !writes.is_empty();
+        self.pending
+            .extend(writes.into_iter().map(|write| (write, None)));
+        if changed && self.retry_at.is_none() {
+            self.flush_writes();
+        }
+        self.check_schedule = false;
+        changed
+    }
+
+    /// Applies every queued change, or schedules another attempt if the database is
+    /// locked.
+    fn flush_writes(&mut self) {
+        let writes: Vec<Write> = self
+            .pending
+            .iter()
+            .map(|(write, _)| write.clone())
+            .collect();
+        match self.store.apply(&writes) {
+            Ok(results) => {
+                // New wake times or reminders may have been written
+                self.check_schedule |= writes
+                    .iter()
+                    .any(|write| matches!(write, Write::SetProperty { .. }));
+                let applied: Vec<_> = self.pending.drain(..).collect();
+                for ((_, done), result) in applied.into_iter().zip(results) {
+                    if let Err(e) = &result {
+                        self.report(e.clone());
+                    }
+                    if let Some(done) = done {
+                        let _ = done.send(result);
+                    }
+                }
+                self.attempts = 0;
+                self.retry_at = None;
+                // Messages may have been marked read or unread, or threads muted
+                self.count_unread();
+            }
+            Err(StoreError::Busy(e)) if self.attempts + 1 < MAX_WRITE_ATTEMPTS => {
+                self.attempts += 1;
+                let backoff = (INITIAL_BACKOFF * 2u32.pow(self.attempts - 1)).min(MAX_BACKOFF);
+                self.retry_at = Some(Instant::now() + backoff);
+                eprintln!("Database is locked, retrying in {:?}: {}", backoff, e);
+            }
+            Err(e) => {
+                let message = format!(
+                    "Gave up on {} database change(s): {}",
+                    self.pending.len(),
+                    e
+                );
+                for (_, done) in self.pending.drain(..) {
+                    if let Some(done) = done {
+                        let _ = done.send(Err(message.clone()));
+                    }
+                }
+                self.report(message);
+                self.attempts = 0;
+                self.retry_at = None;
+            }
+        }
+    }
+}
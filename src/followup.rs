@@ -0,0 +1,177 @@
+//! Follow-up reminders for sent mail. A sent message can ask to come back to the inbox
+//! if nobody has replied to it by a deadline, which is kept in a message property. The
+//! message is also tagged `awaiting-reply`, since properties can only be searched for by
+//! their exact value.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+
+use crate::address;
+use crate::mail::MessageSet;
+use crate::store::{MailStore, MessageInfo, StoreError, Write};
+
+/// The property holding the deadline for a reply, in RFC 3339.
+pub const FOLLOW_UP_BY: &str = "seneca.follow-up-by";
+const AWAITING_REPLY: &str = "awaiting-reply";
+
+/// Asks for the sent message `message_id` to come back if there's no reply by `by`.
+pub fn request(message_id: &str, by: &DateTime<Local>) -> Vec<Write> {
+    let query = format!("id:\"{}\"", message_id.replace('"', "\"\""));
+    vec![
+        Write::SetProperty {
+            query: query.clone(),
+            key: FOLLOW_UP_BY.to_string(),
+            value: Some(by.to_rfc3339()),
+        },
+        Write::Tag {
+            query,
+            add: vec![AWAITING_REPLY.to_string()],
+            remove: Vec::new(),
+        },
+    ]
+}
+
+/// The changes that settle every reminder that has been answered, or has run out of
+/// time, in which case the sent message is put in the inbox tagged `followup`. Also
+/// returns when the next one runs out. Messages from the reminder's sender or from
+/// `own_addresses` don't count as answers.
+pub fn due(
+    store: &mut dyn MailStore,
+    now: &DateTime<Local>,
+    own_addresses: &[String],
+) -> Result<(Vec<Write>, Option<DateTime<Local>>), StoreError> {
+    let mut next: Option<DateTime<Local>> = None;
+    let mut writes = Vec::new();
+    let mut threads: HashMap<String, Vec<MessageInfo>> = HashMap::new();
+    for sent in store.messages(&format!("tag:{}", AWAITING_REPLY))? {
+        let by = sent
+            .properties
+            .iter()
+            .find(|(key, _)| key == FOLLOW_UP_BY)
+            .and_then(|(_, value)| DateTime::parse_from_rfc3339(value).ok())
+            .map(|by| by.with_timezone(&Local));
+        // Tagged by hand rather than by Seneca
+        let by = match by {
+            Some(by) => by,
+            None => continue,
+        };
+
+        if !threads.contains_key(&sent.thread_id) {
+            let messages = store.messages(&format!("thread:{}", sent.thread_id))?;
+            threads.insert(sent.thread_id.clone(), messages);
+        }
+        let sender = address::parse_value(&sent.from);
+        let ours = |email: &str| {
+            sender
+                .iter()
+                .map(|sender| sender.email.as_str())
+                .chain(own_addresses.iter().map(String::as_str))
+                .any(|own| own.eq_ignore_ascii_case(email))
+        };
+        let from_sender = |message: &MessageInfo| {
+            address::parse_value(&message.from)
+                .iter()
+                .any(|from| ours(&from.email))
+        };
+        let replied = threads[&sent.thread_id]
+            .iter()
+            .any(|message| message.date > sent.date && !from_sender(message));
+
+        let add = if replied {
+            Vec::new()
+        } else if by <= *now {
+            vec!["inbox".to_string(), "followup".to_string()]
+        } else {
+            next = Some(next.map_or(by, |next| next.min(by)));
+            continue;
+        };
+        let query = MessageSet {
+            thread: sent.thread_id,
+            messages: vec![sent.id],
+        }
+        .query();
+        writes.push(Write::SetProperty {
+            query: query.clone(),
+            key: FOLLOW_UP_BY.to_string(),
+            value: None,
+        });
+        writes.push(Write::Tag {
+            query,
+            add,
+            remove: vec![AWAITING_REPLY.to_string()],
+        });
+    }
+    Ok((writes, next))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::testing;
+
+    fn now() -> DateTime<Local> {
+        Local.ymd(2021, 6, 16).and_hms(10, 0, 0)
+    }
+
+    /// The fixtures with a reminder on `message_id` that runs out at `by`.
+    fn store_with_reminder(message_id: &str, by: &DateTime<Local>) -> MemoryStore {
+        let mut store = MemoryStore::from_dir(&testing::fixtures());
+        apply(&mut store, &request(message_id, by));
+        store
+    }
+
+    fn apply(store: &mut dyn MailStore, writes: &[Write]) {
+        for result in store.apply(writes).unwrap() {
+            result.unwrap();
+        }
+    }
+
+    fn ids(store: &mut dyn MailStore, query: &str) -> Vec<String> {
+        let messages = store.messages(query).unwrap();
+        messages.into_iter().map(|message| message.id).collect()
+    }
+
+    #[test]
+    fn answered_reminders_are_dropped() {
+        let mut store =
+            store_with_reminder("lunch-reply@example.com", &(now() + Duration::days(1)));
+        let (writes, next) = due(&mut store, &now(), &[]).unwrap();
+        assert_eq!(next, None);
+        apply(&mut store, &writes);
+        assert!(ids(&mut store, "tag:awaiting-reply").is_empty());
+        assert!(ids(&mut store, "tag:followup").is_empty());
+    }
+
+    #[test]
+    fn expired_reminders_come_back_to_the_inbox() {
+        let mut store =
+            store_with_reminder("lunch-reply-2@example.com", &(now() - Duration::hours(1)));
+        let (writes, next) = due(&mut store, &now(), &[]).unwrap();
+        assert_eq!(next, None);
+        apply(&mut store, &writes);
+        assert!(ids(&mut store, "tag:awaiting-reply").is_empty());
+        assert_eq!(
+            ids(&mut store, "tag:followup and tag:inbox"),
+            ["lunch-reply-2@example.com"]
+        );
+    }
+
+    #[test]
+    fn messages_from_any_identity_are_not_answers() {
+        let by = now() + Duration::days(1);
+        let mut store = store_with_reminder("lunch@example.com", &by);
+        let own = ["ADA@example.com".to_string()];
+        let (writes, next) = due(&mut store, &now(), &own).unwrap();
+        assert!(writes.is_empty());
+        assert_eq!(next, Some(by));
+        assert_eq!(ids(&mut store, "tag:awaiting-reply"), ["lunch@example.com"]);
+
+        // Without the identity, Ada's message is a reply
+        let (writes, _) = due(&mut store, &now(), &[]).unwrap();
+        assert!(!writes.is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+use config::{Config, Value};
+use druid::im::Vector;
+use druid::{Data, Lens};
+
+use crate::address::{self, Address};
+use crate::mail::Email;
+use crate::notmuch_config::NotmuchConfig;
+
+const DEFAULT_TRANSPORT: &str = "sendmail -t";
+
+/// One of the addresses the user sends mail as, from an `[[identities]]` entry in
+/// `config.toml` or else from notmuch's `user` settings.
+#[derive(Clone, Data, Lens, Debug, Default)]
+pub struct Identity {
+    pub name: String,
+    pub address: String,
+    /// Appended to new messages below a "-- " separator
+    pub signature: String,
+    /// A sendmail-compatible command that reads the message on stdin, e.g. `msmtp -t`
+    pub transport: String,
+    /// The maildir a copy of each sent message is written to
+    pub sent_folder: Option<String>,
+    /// Tags applied to the sent copy once it is indexed
+    pub sent_tags: Vector<String>,
+    pub default: bool,
+}
+
+impl Identity {
+    /// This identity as an address, for showing in the UI.
+    pub fn as_address(&self) -> Address {
+        Address::new(Some(self.name.clone()), self.address.clone())
+    }
+
+    /// The value of a From header for this identity, with the name encoded if it isn't
+    /// plain ASCII.
+    pub fn mailbox(&self) -> String {
+        if self.name.is_ascii() {
+            self.as_address().to_string()
+        } else {
+            format!("{} <{}>", address::encode_words(&self.name), self.address)
+        }
+    }
+}
+
+fn string_field(table: &std::collections::HashMap<String, Value>, key: &str) -> Option<String> {
+    table
+        .get(key)
+        .and_then(|value| value.clone().into_str().ok())
+}
+
+/// Tags for sent copies when an identity doesn't list its own: whatever notmuch tags new
+/// mail with, except `inbox` and `unread`, plus `sent`.
+fn default_sent_tags(notmuch: &NotmuchConfig) -> Vector<String> {
+    let mut tags: Vector<String> = notmuch
+        .new_tags
+        .iter()
+        .filter(|tag| *tag != "inbox" && *tag != "unread")
+        .cloned()
+        .collect();
+    if !tags.contains(&"sent".to_string()) {
+        tags.push_back("sent".to_string());
+    }
+    tags
+}
+
+/// Reads the `[[identities]]` array from the config file. Without one, there is an
+/// identity for each of the addresses in notmuch's `user.primary_email` and
+/// `user.other_email`.
+pub fn from_config(config: &Config, notmuch: &NotmuchConfig) -> Vector<Identity> {
+    let entries = match config.get_array("identities") {
+        Ok(entries) => entries,
+        Err(_) => return from_notmuch(notmuch),
+    };
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let table = entry.into_table().ok()?;
+            let address = match string_field(&table, "address") {
+                Some(address) => address,
+                None => {
+                    eprintln!("Ignoring an identity with no address in config file.");
+                    return None;
+                }
+            };
+            Some(Identity {
+                name: string_field(&table, "name").unwrap_or_default(),
+                address,
+                signature: string_field(&table, "signature").unwrap_or_default(),
+                transport: string_field(&table, "transport")
+                    .unwrap_or_else(|| DEFAULT_TRANSPORT.to_string()),
+                sent_folder: string_field(&table, "sent-folder"),
+                sent_tags: table
+                    .get("sent-tags")
+                    .and_then(|tags| tags.clone().into_array().ok())
+                    .map(|tags| {
+                        tags.into_iter()
+                            .filter_map(|tag| tag.into_str().ok())
+                            .collect()
+                    })
+                    .unwrap_or_else(|| default_sent_tags(notmuch)),
+                default: table
+                    .get("default")
+                    .and_then(|default| default.clone().into_bool().ok())
+                    .unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+fn from_notmuch(notmuch: &NotmuchConfig) -> Vector<Identity> {
+    notmuch
+        .emails()
+        .enumerate()
+        .map(|(i, address)| Identity {
+            name: notmuch.user_name.clone().unwrap_or_default(),
+            address: address.clone(),
+            signature: String::new(),
+            transport: DEFAULT_TRANSPORT.to_string(),
+            sent_folder: None,
+            sent_tags: default_sent_tags(notmuch),
+            default: i == 0,
+        })
+        .collect()
+}
+
+/// The index of the identity to send new mail from: the one marked `default`, or else
+/// the first one.
+pub fn default_index(identities: &Vector<Identity>) -> usize {
+    identities
+        .iter()
+        .position(|identity| identity.default)
+        .unwrap_or(0)
+}
+
+/// Picks the identity to reply to `email` with, based on which of our addresses it was
+/// sent to.
+pub fn for_reply(identities: &Vector<Identity>, email: &Email) -> usize {
+    email
+        .to
+        .iter()
+        .chain(email.cc.iter())
+        .find_map(|recipient| {
+            identities
+                .iter()
+                .position(|identity| identity.address.eq_ignore_ascii_case(&recipient.email))
+        })
+        .unwrap_or_else(|| default_index(identities))
+}
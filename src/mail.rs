@@ -1,110 +1,626 @@
-use std::ffi::OsString;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
-use druid::im::{vector, Vector};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Offset, TimeZone};
+use druid::im::Vector;
 use druid::lens::InArc;
 use druid::widget::{
-    Container, CrossAxisAlignment, Flex, Label, LineBreaking, List, Padding, Scroll,
+    Button, Container, CrossAxisAlignment, Either, Flex, Label, LineBreaking, List, Padding,
+    Scroll, SizedBox,
+};
+use druid::{
+    ArcStr, BoxConstraints, Data, Env, Event, EventCtx, LayoutCtx, Lens, LifeCycle, LifeCycleCtx,
+    PaintCtx, Size, Target, UpdateCtx, Widget, WidgetExt,
+};
+use mailparse::{dateparse, parse_mail, MailHeader, MailHeaderMap, ParsedMail};
+
+use crate::address::{self, Address};
+use crate::cache::MessageCache;
+use crate::outbox::OUTBOX_TAG;
+use crate::store::{MailStore, MessageSource, StoreError};
+use crate::ui::mark_read::{MarkRead, ReadTracker, SeenCheck};
+use crate::ui::snooze::{snooze_button, snooze_choices};
+use crate::ui::star::Star;
+use crate::{
+    BORDER_COLOR, CANCEL_QUEUED, EDIT_QUEUED, MUTE, REPLY, THREADS_LOADED, THREAD_BACKGROUND_COLOR,
+    UNMUTE, VIEW_SOURCE,
 };
-use druid::{ArcStr, Color, Data, Env, Lens, Widget, WidgetExt};
-use lazy_static::lazy_static;
-use mailparse::{dateparse, parse_mail, MailHeaderMap};
-use notmuch::{Database, DatabaseMode};
-use regex::Regex;
 
-use crate::{MailData, BORDER_COLOR, THREAD_BACKGROUND_COLOR};
+/// Snippets are cut off after this many characters.
+const SNIPPET_LENGTH: usize = 200;
+/// How many snippets are handed to the thread list at a time.
+const SNIPPET_BATCH: usize = 25;
+/// Snippets kept for reuse between searches, before the cache starts over.
+const MAX_CACHED_SNIPPETS: usize = 5000;
+/// The tags kept track of for each message as well as for the thread.
+const MESSAGE_TAGS: &[&str] = &["unread", "flagged"];
+
+/// Where a message's date came from. Anything but the Date header is a guess, and the
+/// reading pane says so.
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum DateSource {
+    Header,
+    /// The newest Received header, added by the server that delivered the message
+    Received,
+    /// The date the mail store has for the message
+    Store,
+    /// When the message's file was last modified
+    File,
+}
+
+impl DateSource {
+    /// Why the date might be wrong, if it might.
+    pub fn warning(&self) -> Option<&'static str> {
+        match self {
+            DateSource::Header => None,
+            DateSource::Received => Some("no usable Date header, going by when it arrived"),
+            DateSource::Store => Some("no usable Date header, going by the mail index"),
+            DateSource::File => Some("no usable Date header, going by the file's timestamp"),
+        }
+    }
+}
 
 #[derive(Data, Lens, Clone)]
 pub struct Email {
+    /// The store's id for the message
+    pub id: String,
+    pub unread: bool,
+    pub flagged: bool,
     pub body: String,
     pub subject: String,
-    pub date: Arc<DateTime<Local>>,
-    pub to: String,
-    pub cc: Vector<String>,
-    pub from: String,
+    /// In the sender's timezone
+    pub date: Arc<DateTime<FixedOffset>>,
+    pub date_source: DateSource,
+    pub from: Vector<Address>,
+    pub to: Vector<Address>,
+    pub cc: Vector<Address>,
+    pub bcc: Vector<Address>,
+    pub reply_to: Vector<Address>,
+    pub headers: Vector<(String, String)>,
+    pub path: Arc<PathBuf>,
+    pub show_headers: bool,
+}
+
+impl Email {
+    /// The field that tracks `tag`, for the tags kept track of per message.
+    fn tag_field(&mut self, tag: &str) -> Option<&mut bool> {
+        match tag {
+            "unread" => Some(&mut self.unread),
+            "flagged" => Some(&mut self.flagged),
+            _ => None,
+        }
+    }
+
+    /// Stands in for a message that couldn't be read or parsed, with `error` for its
+    /// body, so the thread still shows that the message is there.
+    fn unreadable(summary: &MessageSummary, modified: Option<SystemTime>, error: String) -> Self {
+        let (date, date_source) = message_date(&[], summary.date, modified);
+        Email {
+            id: summary.id.clone(),
+            unread: summary.unread,
+            flagged: summary.flagged,
+            body: error,
+            subject: format!("Could not parse {}", summary.path.display()),
+            date: Arc::new(date),
+            date_source,
+            from: Vector::new(),
+            to: Vector::new(),
+            cc: Vector::new(),
+            bcc: Vector::new(),
+            reply_to: Vector::new(),
+            headers: Vector::new(),
+            path: summary.path.clone(),
+            show_headers: false,
+        }
+    }
+
+    /// The decoded value of the first header called `key`, ignoring case.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A thread's authors the way notmuch lists them: those of the messages that matched
+/// the search first, then the rest, each in date order.
+#[derive(Clone, Data, Debug, Default)]
+pub struct Authors {
+    pub matched: Vector<Address>,
+    pub others: Vector<Address>,
+}
+
+impl Authors {
+    /// The authors of a thread's messages, given oldest first along with whether each
+    /// message matched.
+    pub fn from_messages(messages: &[(Address, bool)]) -> Self {
+        // The same person can send with different display names
+        let listed = |list: &Vector<Address>, author: &Address| {
+            list.iter()
+                .any(|other| other.email.eq_ignore_ascii_case(&author.email))
+        };
+        let mut authors = Self::default();
+        for (author, _) in messages.iter().filter(|(_, matched)| *matched) {
+            if !listed(&authors.matched, author) {
+                authors.matched.push_back(author.clone());
+            }
+        }
+        for (author, _) in messages {
+            if !listed(&authors.matched, author) && !listed(&authors.others, author) {
+                authors.others.push_back(author.clone());
+            }
+        }
+        authors
+    }
+
+    /// The author of a message with this From header.
+    pub fn author(from: &str) -> Address {
+        address::parse_value(from)
+            .iter()
+            .next()
+            .cloned()
+            .unwrap_or_else(|| Address::new(None, from.trim()))
+    }
+}
+
+/// What the store says about one of a thread's messages, before its file is parsed.
+#[derive(Clone, Data, Debug)]
+pub struct MessageSummary {
+    pub id: String,
+    pub path: Arc<PathBuf>,
+    /// The store's date for the message, which its own is checked against
+    pub date: i64,
+    pub unread: bool,
+    pub flagged: bool,
+    /// Whether the message matched the search the thread was found by
+    pub matched: bool,
+}
+
+impl MessageSummary {
+    /// The field that tracks `tag`, for the tags kept track of per message.
+    fn tag_field(&mut self, tag: &str) -> Option<&mut bool> {
+        match tag {
+            "unread" => Some(&mut self.unread),
+            "flagged" => Some(&mut self.flagged),
+            _ => None,
+        }
+    }
+}
+
+/// Some of a thread's messages, or all of them when `messages` is empty.
+#[derive(Clone, Debug)]
+pub struct MessageSet {
+    pub thread: String,
+    pub messages: Vec<String>,
+}
+
+impl MessageSet {
+    /// A query for exactly these messages.
+    pub fn query(&self) -> String {
+        if self.messages.is_empty() {
+            return format!("thread:{}", self.thread);
+        }
+        self.messages
+            .iter()
+            .map(|id| format!("id:\"{}\"", id.replace('"', "\"\"")))
+            .collect::<Vec<String>>()
+            .join(" or ")
+    }
 }
 
 #[derive(Clone, Data, Lens)]
 pub struct Thread {
-    pub authors: Vector<String>,
+    pub authors: Authors,
     pub date: Arc<DateTime<Local>>,
     pub subject: String,
-    pub message_paths: Vector<Arc<PathBuf>>,
+    pub summaries: Vector<MessageSummary>,
     pub messages: Vector<Email>,
     pub id: String,
     pub tags: Vector<String>,
+    pub total_messages: u32,
+    /// How many of the messages matched the search the thread was found by
+    pub matched_messages: u32,
+    /// The start of the newest message's body
+    pub snippet: String,
+    /// Some of the thread's messages carry one of the excluded tags
+    pub excluded: bool,
     pub viewing: bool,
+    /// Set while the messages are being parsed in the background
+    pub loading: bool,
+    /// When a snoozed thread goes back in the inbox
+    pub snoozed_until: Option<Arc<DateTime<Local>>>,
+    /// Set while the snooze choices are shown
+    pub choosing_snooze: bool,
+    /// The wake time typed in for a custom snooze
+    pub snooze_time: String,
+}
+
+impl Thread {
+    pub fn has_unread(&self) -> bool {
+        self.summaries.iter().any(|summary| summary.unread)
+    }
+
+    /// Muted threads keep new replies out of the inbox and don't count as unread.
+    pub fn is_muted(&self) -> bool {
+        self.tags.iter().any(|tag| tag == "muted")
+    }
+
+    /// Adds or removes `tag` on the messages `ids`, or every message if there are none,
+    /// and keeps the thread's tags in step.
+    pub fn set_tag(&mut self, ids: &[String], tag: &str, on: bool) {
+        let included = |id: &str| ids.is_empty() || ids.iter().any(|other| other == id);
+        for summary in self.summaries.iter_mut() {
+            if let (true, Some(field)) = (included(&summary.id), summary.tag_field(tag)) {
+                *field = on;
+            }
+        }
+        for message in self.messages.iter_mut() {
+            if let (true, Some(field)) = (included(&message.id), message.tag_field(tag)) {
+                *field = on;
+            }
+        }
+        let tagged = self.tags.iter().any(|other| other == tag);
+        let thread_tagged = if MESSAGE_TAGS.contains(&tag) {
+            self.summaries
+                .iter_mut()
+                .any(|summary| summary.tag_field(tag).map_or(false, |field| *field))
+        } else {
+            // Other tags aren't kept track of per message, so taking one off some of the
+            // messages can't be known to take it off the thread
+            on || (tagged && !ids.is_empty())
+        };
+        if thread_tagged && !tagged {
+            self.tags.push_back(tag.to_string());
+        } else if !thread_tagged && tagged {
+            self.tags.retain(|other| other != tag);
+        }
+    }
+}
+
+pub fn load_mail(
+    query: ArcStr,
+    event_sink: &druid::ExtEventSink,
+    store: &mut dyn MailStore,
+) -> Result<(), StoreError> {
+    let threads: Vector<Arc<Thread>> = store
+        .query_threads(&query)?
+        .into_iter()
+        .map(Arc::new)
+        .collect();
+    let _ = event_sink.submit_command(THREADS_LOADED, threads, Target::Auto);
+    Ok(())
+}
+
+/// The first text/plain part of a message, looking inside nested multiparts.
+fn first_plain_part(parsed: &ParsedMail) -> Option<String> {
+    if parsed.subparts.is_empty() {
+        return if parsed.ctype.mimetype == "text/plain" {
+            parsed.get_body().ok()
+        } else {
+            None
+        };
+    }
+    parsed.subparts.iter().find_map(first_plain_part)
+}
+
+/// The start of a message body on a single line, leaving out quoted text and the
+/// signature.
+pub fn snippet(body: &str) -> String {
+    let mut snippet = String::new();
+    for line in body.lines() {
+        let line = line.trim();
+        if line == "--" {
+            break;
+        }
+        // Skip quotes along with the "On ..., someone wrote:" line introducing them
+        if line.is_empty() || line.starts_with('>') || line.ends_with("wrote:") {
+            continue;
+        }
+        if !snippet.is_empty() {
+            snippet.push(' ');
+        }
+        snippet.push_str(&line.split_whitespace().collect::<Vec<&str>>().join(" "));
+        if snippet.chars().count() >= SNIPPET_LENGTH {
+            break;
+        }
+    }
+    snippet.chars().take(SNIPPET_LENGTH).collect()
+}
+
+/// The snippet of the message stored at `path`, or nothing if it can't be read.
+pub fn read_snippet(source: &dyn MessageSource, path: &Path) -> String {
+    let raw = match source.read(path) {
+        Ok(raw) => raw,
+        Err(_) => return String::new(),
+    };
+    parse_mail(&raw)
+        .ok()
+        .and_then(|parsed| first_plain_part(&parsed))
+        .map(|body| snippet(&body))
+        .unwrap_or_default()
 }
 
-pub fn load_mail(query: ArcStr, event_sink: druid::ExtEventSink, db_location: &OsString) {
-    let db = Database::open(Path::new(db_location), DatabaseMode::ReadWrite).unwrap();
-    let inbox = db.create_query(&query).unwrap();
-    let mut threads = inbox.search_threads().unwrap();
-    let mut thread_tracker = Vector::new();
-
-    for thread in threads.by_ref() {
-        thread_tracker.push_back(Arc::new(Thread {
-            authors: thread.authors().clone().into(),
-            date: Arc::new(Local.timestamp(thread.newest_date(), 0)),
-            subject: thread.subject().clone().into(),
-            message_paths: thread
-                .messages()
-                .map(|m| Arc::new(m.filename().into()))
-                .collect::<Vector<Arc<PathBuf>>>(),
-            messages: Vector::new(),
-            id: thread.id().into(),
-            tags: thread.tags().collect(),
-            viewing: false,
-        }));
-    }
-    event_sink.add_idle_callback(|app_data: &mut MailData| {
-        app_data.threads = thread_tracker;
-        app_data.done_loading = true;
-    });
-}
-
-pub fn load_thread_from_disk(data: Arc<Thread>) -> Arc<Thread> {
+/// Reads the snippets of the threads that don't have one yet, from the top of the list
+/// down so the rows on show fill in first, and hands them to `found` as (thread id,
+/// snippet) a batch at a time. Snippets already read are taken from `cache`, by the id
+/// of the message they come from. Stops as soon as `generation` moves on from
+/// `requested`, which means there's a newer list of threads.
+pub fn load_snippets(
+    threads: &Vector<Arc<Thread>>,
+    source: &dyn MessageSource,
+    cache: &Mutex<HashMap<String, String>>,
+    generation: &AtomicU64,
+    requested: u64,
+    mut found: impl FnMut(Vec<(String, String)>),
+) {
+    let mut batch = Vec::new();
+    for thread in threads.iter().filter(|thread| thread.snippet.is_empty()) {
+        if generation.load(Ordering::SeqCst) != requested {
+            return;
+        }
+        let newest = match thread.summaries.iter().max_by_key(|summary| summary.date) {
+            Some(newest) => newest,
+            None => continue,
+        };
+        let cached = cache.lock().unwrap().get(&newest.id).cloned();
+        let snippet = cached.unwrap_or_else(|| {
+            let snippet = read_snippet(source, &newest.path);
+            let mut cache = cache.lock().unwrap();
+            if cache.len() >= MAX_CACHED_SNIPPETS {
+                cache.clear();
+            }
+            cache.insert(newest.id.clone(), snippet.clone());
+            snippet
+        });
+        if !snippet.is_empty() {
+            batch.push((thread.id.clone(), snippet));
+        }
+        if batch.len() >= SNIPPET_BATCH {
+            found(std::mem::take(&mut batch));
+        }
+    }
+    if !batch.is_empty() {
+        found(batch);
+    }
+}
+
+/// Parses an email date, keeping its timezone where it has one.
+fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    // chrono doesn't take comments such as the "(PDT)" after some zones
+    let value = value.split('(').next().unwrap_or_default().trim();
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .or_else(|| {
+            dateparse(value)
+                .ok()
+                .map(|timestamp| FixedOffset::east(0).timestamp(timestamp, 0))
+        })
+        // Zero is what broken dates tend to turn into
+        .filter(|date| date.timestamp() > 0)
+}
+
+/// A message's date from its Date header, or failing that its newest Received header,
+/// the date the store has for it (`stored`) or when it was last modified.
+fn message_date(
+    headers: &[MailHeader],
+    stored: i64,
+    modified: Option<SystemTime>,
+) -> (DateTime<FixedOffset>, DateSource) {
+    if let Some(date) = headers
+        .get_first_value("Date")
+        .and_then(|value| parse_date(&value))
+    {
+        return (date, DateSource::Header);
+    }
+    // Received headers end with "; <date>"
+    let received = headers
+        .get_first_value("Received")
+        .and_then(|value| value.rsplit(';').next().and_then(parse_date));
+    if let Some(date) = received {
+        return (date, DateSource::Received);
+    }
+    if stored > 0 {
+        let date = Local.timestamp(stored, 0);
+        return (date.with_timezone(&date.offset().fix()), DateSource::Store);
+    }
+    let modified: DateTime<Local> = modified
+        .map(DateTime::from)
+        .unwrap_or_else(|| Local.timestamp(0, 0));
+    (
+        modified.with_timezone(&modified.offset().fix()),
+        DateSource::File,
+    )
+}
+
+/// Parses the message behind `summary`. Messages needn't be UTF-8, so they're read as
+/// bytes and left to the parser to decode.
+fn load_email(
+    summary: &MessageSummary,
+    source: &dyn MessageSource,
+    modified: Option<SystemTime>,
+) -> Result<Email, String> {
+    let mail = &summary.path;
+    let raw = source
+        .read(mail)
+        .map_err(|e| format!("Unable to read {}: {}", mail.display(), e))?;
+    let parsed =
+        parse_mail(&raw).map_err(|e| format!("Unable to parse {}: {}", mail.display(), e))?;
+    let (date, date_source) = message_date(&parsed.headers, summary.date, modified);
+    Ok(Email {
+        id: summary.id.clone(),
+        unread: summary.unread,
+        flagged: summary.flagged,
+        body: if parsed.ctype.mimetype.contains("multipart") {
+            let mut body_temp = "Multipart!".to_string();
+            for part in parsed.subparts {
+                if part.ctype.mimetype.contains("plain") {
+                    body_temp = part.get_body().unwrap_or_default();
+                }
+            }
+            body_temp
+        } else {
+            parsed.get_body().unwrap_or_default()
+        },
+        subject: parsed
+            .headers
+            .get_first_value("Subject")
+            .unwrap_or_default(),
+        date: Arc::new(date),
+        date_source,
+        from: address::parse_all(&parsed.headers, "From"),
+        to: address::parse_all(&parsed.headers, "To"),
+        cc: address::parse_all(&parsed.headers, "Cc"),
+        bcc: address::parse_all(&parsed.headers, "Bcc"),
+        reply_to: address::parse_all(&parsed.headers, "Reply-To"),
+        headers: parsed
+            .headers
+            .iter()
+            .map(|header| (header.get_key(), header.get_value()))
+            .collect(),
+        path: mail.clone(),
+        show_headers: false,
+    })
+}
+
+/// Parses every message in the thread, reading them from `source` and reusing cached
+/// messages that haven't changed. Gives up and returns `None` as soon as `generation`
+/// moves on from `requested`, which means the user has asked for a different thread in
+/// the meantime.
+pub fn load_thread(
+    data: Arc<Thread>,
+    source: &dyn MessageSource,
+    cache: &Mutex<MessageCache>,
+    generation: &AtomicU64,
+    requested: u64,
+) -> Option<Arc<Thread>> {
     let mut new_thread = (*data).clone();
-    for mail in data.message_paths.clone() {
-        let raw = std::fs::read_to_string(&*mail).unwrap_or_default();
-        let parsed = parse_mail(raw.as_bytes()).unwrap();
-        new_thread.messages.push_back(Email {
-            body: if parsed.ctype.mimetype.contains("multipart") {
-                let mut body_temp = "Multipart!".to_string();
-                for part in parsed.subparts {
-                    if part.ctype.mimetype.contains("plain") {
-                        body_temp = part.get_body().unwrap_or_default();
+    new_thread.messages = Vector::new();
+    for summary in data.summaries.iter() {
+        let mail = &summary.path;
+        if generation.load(Ordering::SeqCst) != requested {
+            return None;
+        }
+        let modified = source.modified(mail);
+        let cached = modified.and_then(|modified| cache.lock().unwrap().get(mail, modified));
+        let email = match cached {
+            // The file stays the same when the message is only retagged
+            Some(email) => Email {
+                id: summary.id.clone(),
+                unread: summary.unread,
+                flagged: summary.flagged,
+                ..email
+            },
+            None => match load_email(summary, source, modified) {
+                Ok(email) => {
+                    if let Some(modified) = modified {
+                        cache
+                            .lock()
+                            .unwrap()
+                            .insert((**mail).clone(), modified, email.clone());
                     }
+                    email
                 }
-                body_temp
-            } else {
-                parsed.get_body().unwrap_or_default()
+                // Show where the message is rather than lose the rest of the thread
+                Err(e) => Email::unreadable(summary, modified, e),
             },
-            subject: parsed
-                .headers
-                .get_first_value("Subject")
-                .unwrap_or_default(),
-            date: Arc::new(Local.timestamp(
-                dateparse(parsed.headers.get_first_value("Date").unwrap().as_str()).unwrap(),
-                0,
-            )),
-            to: parsed.headers.get_first_value("To").unwrap_or_default(),
-            from: parsed.headers.get_first_value("From").unwrap_or_default(),
-            cc: vector![parsed.headers.get_first_value("Cc").unwrap_or_default()],
-        });
+        };
+        new_thread.messages.push_back(email);
     }
+    new_thread.loading = false;
 
-    Arc::new(new_thread.to_owned())
+    Some(Arc::new(new_thread))
 }
 
-pub fn mail_layout() -> impl Widget<Arc<Thread>> {
-    lazy_static! {
-        static ref NAME_REGEX: Regex =
-            Regex::new(r"'?(\w+(?:\s+\w+)*)'?\s+<?(\S+@[\w.-]+\.[a-zA-Z]{2,4}\b)").unwrap();
+/// What the date label shows: the message's date, where it came from and whether the
+/// mouse is over it.
+type DateView = (Arc<DateTime<FixedOffset>>, DateSource, bool);
+
+/// A message's date in local time, or in the sender's while the mouse is over it, with a
+/// warning when it had to be guessed.
+fn date_text((date, source, hovered): &DateView) -> String {
+    let format = crate::dates::DATE_FORMAT.read().unwrap();
+    let mut text = if *hovered {
+        format!("{} {}", format.long(&**date), date.format("UTC%:z"))
+    } else {
+        format.long(&date.with_timezone(&Local))
+    };
+    if let Some(warning) = source.warning() {
+        text.push_str(" ⚠ ");
+        text.push_str(warning);
     }
-    Scroll::new(
+    text
+}
+
+/// The date label, which keeps track of whether the mouse is over it.
+struct DateHover {
+    hovered: bool,
+    /// What the label was last updated with
+    shown: bool,
+    label: Label<DateView>,
+}
+
+impl DateHover {
+    fn new() -> Self {
+        Self {
+            hovered: false,
+            shown: false,
+            label: Label::new(|view: &DateView, _env: &Env| date_text(view))
+                .with_font(crate::METADATA_FONT),
+        }
+    }
+}
+
+fn date_view(mail: &Email, hovered: bool) -> DateView {
+    (mail.date.clone(), mail.date_source, hovered)
+}
+
+impl Widget<Email> for DateHover {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Email, env: &Env) {
+        // Widgets get one last move event as the mouse leaves them
+        if let Event::MouseMove(_) = event {
+            if self.hovered != ctx.is_hot() {
+                self.hovered = ctx.is_hot();
+                ctx.request_update();
+            }
+        }
+        let mut view = date_view(data, self.shown);
+        self.label.event(ctx, event, &mut view, env);
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &Email, env: &Env) {
+        self.label
+            .lifecycle(ctx, event, &date_view(data, self.shown), env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Email, data: &Email, env: &Env) {
+        let old = date_view(old_data, self.shown);
+        self.shown = self.hovered;
+        self.label
+            .update(ctx, &old, &date_view(data, self.shown), env);
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &Email,
+        env: &Env,
+    ) -> Size {
+        self.label
+            .layout(ctx, bc, &date_view(data, self.shown), env)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Email, env: &Env) {
+        self.label.paint(ctx, &date_view(data, self.shown), env);
+    }
+}
+
+pub fn mail_layout(mark_read: MarkRead) -> impl Widget<Arc<Thread>> {
+    let messages = Scroll::new(
         List::new(|| {
             Flex::column()
                 .cross_axis_alignment(CrossAxisAlignment::Start)
@@ -113,36 +629,93 @@ pub fn mail_layout() -> impl Widget<Arc<Thread>> {
                     Container::new(
                         Flex::column()
                             .cross_axis_alignment(CrossAxisAlignment::Start)
+                            .with_child(
+                                Flex::row()
+                                    .with_child(Star.lens(Email::flagged))
+                                    .with_spacer(4.)
+                                    .with_child(
+                                        Label::new(|mail: &Email, _env: &Env| {
+                                            address::display_list(&mail.from)
+                                        })
+                                        .with_font(crate::UI_FONT),
+                                    ),
+                            )
                             .with_child(
                                 Label::new(|mail: &Email, _env: &Env| {
-                                    let name_text;
-                                    if let Some(caps) = NAME_REGEX.captures(&mail.from) {
-                                        name_text = match caps.get(1) {
-                                            Some(name) => name.as_str(),
-                                            None => &mail.from,
-                                        };
+                                    if mail.cc.is_empty() {
+                                        format!("To: {}", address::display_list(&mail.to))
                                     } else {
-                                        name_text = &mail.from;
+                                        format!(
+                                            "To: {}  Cc: {}",
+                                            address::display_list(&mail.to),
+                                            address::display_list(&mail.cc)
+                                        )
                                     }
-
-                                    format!("{}", name_text)
                                 })
-                                .with_text_color(Color::BLACK)
-                                .with_font(crate::UI_FONT),
+                                .with_font(crate::METADATA_FONT)
+                                .with_line_break_mode(LineBreaking::WordWrap),
                             )
                             .with_child(
                                 Label::new(|mail: &Email, _env: &Env| format!("{}", mail.subject))
-                                    .with_text_color(Color::BLACK)
-                                    .with_font(crate::UI_FONT)
-                                    .with_text_size(18.),
+                                    .with_font(crate::SUBJECT_FONT),
                             )
+                            .with_child(DateHover::new())
                             .with_child(
-                                Label::new(|mail: &Email, _env: &Env| {
-                                    format!("{}", mail.date.format("%Y-%m-%d %H:%M:%S").to_string())
+                                Flex::row()
+                                    .with_child(
+                                        Button::dynamic(|mail: &Email, _env: &Env| {
+                                            if mail.show_headers {
+                                                "Hide headers".to_string()
+                                            } else {
+                                                "Show all headers".to_string()
+                                            }
+                                        })
+                                        .on_click(
+                                            |_ctx, mail: &mut Email, _env| {
+                                                mail.show_headers = !mail.show_headers;
+                                            },
+                                        ),
+                                    )
+                                    .with_spacer(5.)
+                                    .with_child(Button::new("View source").on_click(
+                                        |ctx, mail: &mut Email, _env| {
+                                            ctx.submit_command(VIEW_SOURCE.with(mail.path.clone()));
+                                        },
+                                    ))
+                                    .with_spacer(5.)
+                                    .with_child(Button::new("Reply").on_click(
+                                        |ctx, mail: &mut Email, _env| {
+                                            ctx.submit_command(REPLY.with(mail.clone()));
+                                        },
+                                    ))
+                                    .with_spacer(5.)
+                                    .with_child(
+                                        Button::dynamic(|mail: &Email, _env: &Env| {
+                                            if mail.unread {
+                                                "Mark read".to_string()
+                                            } else {
+                                                "Mark unread".to_string()
+                                            }
+                                        })
+                                        .on_click(
+                                            |_ctx, mail: &mut Email, _env| {
+                                                mail.unread = !mail.unread;
+                                            },
+                                        ),
+                                    ),
+                            )
+                            .with_child(Either::new(
+                                |mail: &Email, _env| mail.show_headers,
+                                List::new(|| {
+                                    Label::new(|header: &(String, String), _env: &Env| {
+                                        format!("{}: {}", header.0, header.1)
+                                    })
+                                    .with_font(crate::METADATA_FONT)
+                                    .with_line_break_mode(LineBreaking::WordWrap)
                                 })
-                                .with_text_color(Color::BLACK)
-                                .with_font(crate::UI_FONT_LIGHT),
-                            ),
+                                .lens(Email::headers),
+                                SizedBox::empty(),
+                            )),
                     )
                     .expand_width()
                     .background(THREAD_BACKGROUND_COLOR)
@@ -155,6 +728,7 @@ pub fn mail_layout() -> impl Widget<Arc<Thread>> {
                             Label::new(|mail: &Email, _env: &Env| {
                                 format!("{}", august::convert(&mail.body, 80))
                             })
+                            .with_font(crate::BODY_FONT)
                             .with_line_break_mode(LineBreaking::WordWrap),
                         )
                         .vertical(),
@@ -164,9 +738,73 @@ pub fn mail_layout() -> impl Widget<Arc<Thread>> {
                     //.border(BORDER_COLOR, 1.5)
                     //.rounded(2.),
                 ))
+                .controller(SeenCheck)
         })
         .lens(InArc::new(Thread::messages)),
     )
     .vertical()
-    .border(Color::RED, 0.1)
+    .border(BORDER_COLOR, 0.1);
+
+    let thread_actions = Flex::row()
+        .with_child(
+            Button::dynamic(|thread: &Arc<Thread>, _env: &Env| {
+                if thread.messages.iter().any(|mail| mail.unread) {
+                    "Mark all read".to_string()
+                } else {
+                    "Mark unread".to_string()
+                }
+            })
+            .on_click(|_ctx, thread: &mut Arc<Thread>, _env| {
+                let unread = !thread.messages.iter().any(|mail| mail.unread);
+                Arc::make_mut(thread).set_tag(&[], "unread", unread);
+            }),
+        )
+        .with_spacer(5.)
+        .with_child(
+            Button::dynamic(|thread: &Arc<Thread>, _env: &Env| {
+                if thread.is_muted() {
+                    "Unmute".to_string()
+                } else {
+                    "Mute".to_string()
+                }
+            })
+            .on_click(|ctx, thread: &mut Arc<Thread>, _env| {
+                let command = if thread.is_muted() { UNMUTE } else { MUTE };
+                ctx.submit_command(command.with(MessageSet {
+                    thread: thread.id.clone(),
+                    messages: Vec::new(),
+                }));
+            }),
+        )
+        .with_spacer(5.)
+        .with_child(snooze_button())
+        .with_child(Either::new(
+            |thread: &Arc<Thread>, _env| thread.tags.iter().any(|tag| tag == OUTBOX_TAG),
+            Flex::row()
+                .with_spacer(5.)
+                .with_child(
+                    Button::new("Edit").on_click(|ctx, thread: &mut Arc<Thread>, _env| {
+                        ctx.submit_command(EDIT_QUEUED.with(thread.clone()));
+                    }),
+                )
+                .with_spacer(5.)
+                .with_child(Button::new("Cancel sending").on_click(
+                    |ctx, thread: &mut Arc<Thread>, _env| {
+                        ctx.submit_command(CANCEL_QUEUED.with(thread.clone()));
+                    },
+                )),
+            SizedBox::empty(),
+        ));
+
+    ReadTracker::new(
+        Either::new(
+            |thread: &Arc<Thread>, _env| thread.loading,
+            Label::new("Loading messages...").center(),
+            Flex::column()
+                .with_child(thread_actions.padding(5.))
+                .with_child(snooze_choices().padding((5., 0., 5., 5.)))
+                .with_flex_child(messages, 1.0),
+        ),
+        mark_read,
+    )
 }
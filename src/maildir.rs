@@ -0,0 +1,50 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::Local;
+
+static DELIVERIES: AtomicUsize = AtomicUsize::new(0);
+
+fn hostname() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "seneca".to_string())
+        // '/' and ':' have special meaning in maildir filenames
+        .replace('/', "\\057")
+        .replace(':', "\\072")
+}
+
+/// A filename that is unique within a maildir, following the usual
+/// `time.pid_counter.host` scheme.
+pub fn unique_name() -> String {
+    format!(
+        "{}.{}_{}.{}",
+        Local::now().timestamp(),
+        std::process::id(),
+        DELIVERIES.fetch_add(1, Ordering::SeqCst),
+        hostname()
+    )
+}
+
+/// Writes `contents` into the `cur` directory of the maildir at `folder` with the given
+/// flags (e.g. `"S"` for seen). The message is written to `tmp` first and renamed into
+/// place so other clients never see a partial file.
+pub fn deliver(folder: &Path, flags: &str, contents: &[u8]) -> io::Result<PathBuf> {
+    for subdir in &["cur", "new", "tmp"] {
+        fs::create_dir_all(folder.join(subdir))?;
+    }
+    let name = unique_name();
+    let tmp_path = folder.join("tmp").join(&name);
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    let cur_path = folder.join("cur").join(format!("{}:2,{}", name, flags));
+    fs::rename(&tmp_path, &cur_path)?;
+    Ok(cur_path)
+}
@@ -1,52 +1,482 @@
+use std::collections::HashMap;
 use std::ffi::OsString;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
+use chrono::{DateTime, Local};
 use config::{Config, File, FileFormat};
 use dirs::config_dir;
-use druid::im::{vector, Vector};
-use druid::widget::{prelude::*, Split};
-use druid::widget::{Container, Either, Label, Maybe, Scroll, WidgetExt};
+use druid::im::{self, vector, Vector};
+use druid::widget::prelude::*;
+use druid::widget::{Container, Either, Label, Scroll, ViewSwitcher, WidgetExt};
 use druid::{
-    AppDelegate, AppLauncher, ArcStr, Color, Command, Data, DelegateCtx, FontDescriptor,
-    FontFamily, FontWeight, Handled, Key, Lens, Selector, Target, WindowDesc,
+    AppDelegate, AppLauncher, ArcStr, Color, Command, Data, DelegateCtx, FontDescriptor, Handled,
+    Key, Lens, Selector, Target, WindowDesc, WindowId,
 };
-use notmuch::{Database, DatabaseMode};
 
+mod address;
+mod cache;
+mod compose;
+mod contacts;
+mod dates;
+mod db;
+mod followup;
+mod identity;
 mod mail;
+mod maildir;
+mod notmuch_config;
+mod outbox;
+mod snooze;
+mod state;
+mod store;
+mod tags;
+#[cfg(test)]
+mod testing;
+mod theme;
+mod transport;
 mod ui;
 
-use crate::mail::Thread;
+use crate::cache::MessageCache;
+use crate::compose::{Draft, DraftWorker};
+use crate::db::DbHandle;
+use crate::identity::Identity;
+use crate::mail::{Email, MessageSet, Thread};
+use crate::notmuch_config::NotmuchConfig;
+use crate::outbox::OutboxHandle;
+use crate::state::{SaveSession, SessionState};
+use crate::store::{MailStore, MemoryStore, NotmuchStore, Write};
+use crate::tags::TagStyles;
+use crate::theme::Themes;
+use crate::ui::mark_read::MarkRead;
+use crate::ui::reading_pane::ReadingPane;
+use crate::ui::source_view::SourceView;
+use crate::ui::split::Split;
+use crate::ui::tabs::{TabShortcuts, Tabs};
 
 const SEARCH_CHANGE: Selector<ArcStr> = Selector::new("search-change");
+const SHOW_EXCLUDED: Selector<bool> = Selector::new("show-excluded");
+const THREADS_LOADED: Selector<Vector<Arc<Thread>>> = Selector::new("threads-loaded");
 const LOAD_THREAD: Selector<Arc<Thread>> = Selector::new("load-thread");
-const MARK_READ: Selector<Arc<Thread>> = Selector::new("mark-read");
+const THREAD_LOADED: Selector<Arc<Thread>> = Selector::new("thread-loaded");
+/// Snippets for the thread list, as (thread id, snippet)
+const SNIPPETS_LOADED: Selector<Vec<(String, String)>> = Selector::new("snippets-loaded");
+const OPEN_TAB: Selector<Arc<Thread>> = Selector::new("open-tab");
+const SELECT_TAB: Selector<String> = Selector::new("select-tab");
+const CLOSE_TAB: Selector<String> = Selector::new("close-tab");
+const REOPEN_TAB: Selector = Selector::new("reopen-tab");
+const MARK_READ: Selector<MessageSet> = Selector::new("mark-read");
+const MARK_UNREAD: Selector<MessageSet> = Selector::new("mark-unread");
+const FLAG: Selector<MessageSet> = Selector::new("flag");
+const UNFLAG: Selector<MessageSet> = Selector::new("unflag");
+const MUTE: Selector<MessageSet> = Selector::new("mute");
+const UNMUTE: Selector<MessageSet> = Selector::new("unmute");
+/// Snoozes a thread, by id, until the time given
+const SNOOZE: Selector<(String, DateTime<Local>)> = Selector::new("snooze");
+const UNSNOOZE: Selector<String> = Selector::new("unsnooze");
+const VIEW_SOURCE: Selector<Arc<PathBuf>> = Selector::new("view-source");
+const COMPOSE_NEW: Selector = Selector::new("compose-new");
+const REPLY: Selector<Email> = Selector::new("reply");
+const SEND_DRAFT: Selector<u64> = Selector::new("send-draft");
+/// Queues a draft in the outbox to be sent at the time typed into it
+const SEND_LATER: Selector<u64> = Selector::new("send-later");
+/// Takes a thread's queued messages out of the outbox and opens them for editing
+const EDIT_QUEUED: Selector<Arc<Thread>> = Selector::new("edit-queued");
+const CANCEL_QUEUED: Selector<Arc<Thread>> = Selector::new("cancel-queued");
+const DISCARD_DRAFT: Selector<u64> = Selector::new("discard-draft");
+const SAVE_DRAFT: Selector<u64> = Selector::new("save-draft");
 const UI_FONT: Key<FontDescriptor> = Key::new("org.westwork.seneca.ui-font");
 const UI_FONT_LARGE: Key<FontDescriptor> = Key::new("org.westwork.seneca.ui-font-large");
 const UI_FONT_LIGHT: Key<FontDescriptor> = Key::new("org.westwork.seneca.ui-font-light");
+const SUBJECT_FONT: Key<FontDescriptor> = Key::new("org.westwork.seneca.subject-font");
+const METADATA_FONT: Key<FontDescriptor> = Key::new("org.westwork.seneca.metadata-font");
+const BODY_FONT: Key<FontDescriptor> = Key::new("org.westwork.seneca.body-font");
+const MONOSPACE_FONT: Key<FontDescriptor> = Key::new("org.westwork.seneca.monospace-font");
+/// The height of a row in the thread list, which follows the font sizes
+const THREAD_HEIGHT: Key<f64> = Key::new("org.westwork.seneca.thread-height");
 const THREAD_BACKGROUND_COLOR: Key<Color> = Key::new("org.westwork.seneca.background-color");
 const THREAD_SELECTED_COLOR: Key<Color> = Key::new("org.westwork.seneca.thread-selected-color");
 const BORDER_COLOR: Key<Color> = Key::new("org.westwork.seneca.border-color");
 const SEARCH_BACKGROUND_COLOR: Key<Color> = Key::new("org.westwork.seneca.search-background-color");
 const SEARCH_SELECTED_COLOR: Key<Color> = Key::new("org.westwork.seneca.search-selected-color");
+const SEARCH_TEXT_COLOR: Key<Color> = Key::new("org.westwork.seneca.search-text-color");
+const SECONDARY_TEXT_COLOR: Key<Color> = Key::new("org.westwork.seneca.secondary-text-color");
+const UNREAD_COLOR: Key<Color> = Key::new("org.westwork.seneca.unread-color");
+const ERROR_COLOR: Key<Color> = Key::new("org.westwork.seneca.error-color");
+const FLAGGED_COLOR: Key<Color> = Key::new("org.westwork.seneca.flagged-color");
+/// The background of tag pills, for tags without a colour of their own
+const TAG_COLOR: Key<Color> = Key::new("org.westwork.seneca.tag-color");
+const TAG_TEXT_COLOR: Key<Color> = Key::new("org.westwork.seneca.tag-text-color");
 
 #[derive(Data, Lens, Clone)]
 pub struct MailData {
     threads: Vector<Arc<Thread>>,
     searches: Searches,
+    query: String,
+    /// The most recent error worth telling the user about
+    status: String,
     done_loading: bool,
-    loaded_thread: Option<Arc<Thread>>,
+    /// Whether searches include messages with one of the excluded tags
+    show_excluded: bool,
+    /// The threads open in the reading pane
+    tabs: Tabs,
+    source_views: im::HashMap<Arc<PathBuf>, SourceView>,
+    identities: Vector<Identity>,
+    drafts: im::HashMap<u64, Draft>,
+    /// The name of the current theme
+    theme: ArcStr,
+    theme_names: Vector<ArcStr>,
+    /// How much larger than configured every font is drawn
+    zoom: f64,
+    /// The share of the window given to the search sidebar
+    sidebar_split: f64,
+    /// The share of the rest given to the thread list
+    list_split: f64,
+    /// The share of the rest given to the thread list when the reading pane is below it
+    bottom_split: f64,
+    reading_pane: ReadingPane,
+    /// Threads open in windows of their own, by thread id
+    thread_windows: im::HashMap<String, Arc<Thread>>,
 }
 
 #[derive(Data, Lens, Clone)]
 pub struct Searches {
-    search_list: Vector<(ArcStr, ArcStr)>,
+    /// The name and query of each search, and how many unread messages it finds
+    search_list: Vector<(ArcStr, ArcStr, u32)>,
     selected: ArcStr,
 }
 
 struct Delegate {
-    database: OsString,
+    db: DbHandle,
+    source_windows: HashMap<WindowId, Arc<PathBuf>>,
+    thread_windows: HashMap<WindowId, String>,
+    compose_windows: HashMap<WindowId, u64>,
+    next_draft: u64,
+    /// The thread doing each open draft's disk work, by draft id
+    draft_workers: HashMap<u64, DraftWorker>,
+    /// Each open draft as it was last saved or opened, to tell whether it has been edited
+    saved_drafts: HashMap<u64, Draft>,
+    drafts_folder: Option<PathBuf>,
+    /// Sends what's queued in the `outbox-folder`, if there is one
+    outbox: Option<OutboxHandle>,
+    message_cache: Arc<Mutex<MessageCache>>,
+    /// Thread list snippets by message id, and the generation of the search they're
+    /// being read for
+    snippets: Arc<Mutex<HashMap<String, String>>>,
+    snippet_generation: Arc<AtomicU64>,
+    /// The generation of every thread load under way, by thread id. Bumping one stops
+    /// that load early.
+    loads: HashMap<String, Arc<AtomicU64>>,
+    themes: Arc<Themes>,
+    mark_read: MarkRead,
+    /// The thread that was open last time, to reopen once the first search is in
+    restore_thread: Option<String>,
+}
+
+impl Delegate {
+    fn open_compose(&mut self, ctx: &mut DelegateCtx, data: &mut MailData, draft: Draft) -> u64 {
+        let id = self.next_draft;
+        self.next_draft += 1;
+        let title = if draft.subject.is_empty() {
+            "New message".to_string()
+        } else {
+            draft.subject.clone()
+        };
+        let saved = draft.path.as_ref().map(|path| path.to_path_buf());
+        self.draft_workers.insert(id, DraftWorker::spawn(saved));
+        self.saved_drafts.insert(id, draft.clone());
+        data.drafts.insert(id, draft);
+        let window = WindowDesc::new(theme::themed(
+            &self.themes,
+            ui::compose::compose_window(id, &data.identities),
+        ))
+        .title(title)
+        .window_size((700.0, 600.0));
+        self.compose_windows.insert(window.id, id);
+        ctx.new_window(window);
+        id
+    }
+
+    /// Saves a draft to the drafts maildir in the background and records where it went.
+    fn save_draft(&mut self, ctx: &mut DelegateCtx, data: &MailData, id: u64, draft: Draft) {
+        let folder = match &self.drafts_folder {
+            Some(folder) => folder.clone(),
+            None => return,
+        };
+        let worker = match self.draft_workers.get(&id) {
+            Some(worker) => worker,
+            None => return,
+        };
+        let identity = data
+            .identities
+            .get(draft.identity)
+            .cloned()
+            .unwrap_or_default();
+        self.saved_drafts.insert(id, draft.clone());
+        let db = self.db.clone();
+        let event_sink = ctx.get_external_handle();
+        worker.run(move |saved| {
+            let result = compose::save_draft(&draft, &identity, &folder, saved, &db);
+            event_sink.add_idle_callback(move |data: &mut MailData| {
+                if let Some(draft) = data.drafts.get_mut(&id) {
+                    match result {
+                        Ok(path) => {
+                            draft.path = Some(Arc::new(path));
+                            draft.status =
+                                format!("Draft saved at {}", chrono::Local::now().format("%H:%M"));
+                        }
+                        Err(e) => draft.status = e,
+                    }
+                } else if let Err(e) = result {
+                    eprintln!("{}", e);
+                }
+            });
+        });
+    }
+
+    /// Opens an editor for every message in `thread` that lives in the drafts maildir
+    /// and isn't already being edited.
+    fn open_saved_drafts(&mut self, ctx: &mut DelegateCtx, data: &mut MailData, thread: &Thread) {
+        let folder = match &self.drafts_folder {
+            Some(folder) => folder.clone(),
+            None => return,
+        };
+        if !thread.tags.contains(&"draft".to_string()) {
+            return;
+        }
+        for email in thread.messages.iter() {
+            let already_open = data
+                .drafts
+                .values()
+                .any(|draft| draft.path.as_ref() == Some(&email.path));
+            if email.path.starts_with(&folder) && !already_open {
+                let draft = Draft::from_saved(email, &data.identities);
+                self.open_compose(ctx, data, draft);
+            }
+        }
+    }
+
+    /// Opens `thread` in a window of its own, or brings its window forward if it already
+    /// has one, and parses its messages in the background.
+    fn open_thread_window(
+        &mut self,
+        ctx: &mut DelegateCtx,
+        data: &mut MailData,
+        thread: &Arc<Thread>,
+    ) {
+        let existing = self
+            .thread_windows
+            .iter()
+            .find(|(_, id)| **id == thread.id)
+            .map(|(window, _)| *window);
+        if let Some(window) = existing {
+            ctx.submit_command(druid::commands::SHOW_WINDOW.to(window));
+            return;
+        }
+
+        data.thread_windows
+            .insert(thread.id.clone(), placeholder(thread));
+        let window = WindowDesc::new(theme::themed(
+            &self.themes,
+            ui::reading_pane::thread_window(thread.id.clone(), self.mark_read),
+        ))
+        .title(thread.subject.clone())
+        .window_size((700.0, 700.0));
+        self.thread_windows.insert(window.id, thread.id.clone());
+        ctx.new_window(window);
+        self.start_load(ctx, thread);
+    }
+
+    /// Parses `thread`'s messages in the background, unless that's already under way.
+    fn start_load(&mut self, ctx: &mut DelegateCtx, thread: &Arc<Thread>) {
+        if self.loads.contains_key(&thread.id) {
+            return;
+        }
+        let generation = Arc::new(AtomicU64::new(0));
+        self.loads.insert(thread.id.clone(), generation.clone());
+        let source = self.db.source();
+        let cache = self.message_cache.clone();
+        let to_load = thread.clone();
+        let event_sink = ctx.get_external_handle();
+        thread::spawn(move || {
+            let loaded = mail::load_thread(to_load, &*source, &cache, &generation, 0);
+            if let Some(loaded) = loaded {
+                let _ = event_sink.submit_command(THREAD_LOADED, loaded, Target::Auto);
+            }
+        });
+    }
+
+    /// Reads the snippets for a new thread list in the background, giving up on the
+    /// ones for the previous list.
+    fn load_snippets(&mut self, ctx: &mut DelegateCtx, threads: &Vector<Arc<Thread>>) {
+        let requested = self.snippet_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let generation = self.snippet_generation.clone();
+        let source = self.db.source();
+        let cache = self.snippets.clone();
+        let threads = threads.clone();
+        let event_sink = ctx.get_external_handle();
+        thread::spawn(move || {
+            mail::load_snippets(
+                &threads,
+                &*source,
+                &cache,
+                &generation,
+                requested,
+                |snippets| {
+                    let _ = event_sink.submit_command(SNIPPETS_LOADED, snippets, Target::Auto);
+                },
+            );
+        });
+    }
+
+    /// Stops loading the thread `id` if no tab or window is waiting for it any more.
+    fn cancel_load(&mut self, data: &MailData, id: &str) {
+        if data.tabs.is_open(id) || data.thread_windows.contains_key(id) {
+            return;
+        }
+        if let Some(generation) = self.loads.remove(id) {
+            generation.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Adds or removes `tag` on the messages in `set`, and brings every copy of their
+    /// thread up to date.
+    fn set_tag(&self, data: &mut MailData, set: &MessageSet, tag: &str, on: bool) {
+        let tags = vec![tag.to_string()];
+        let (add, remove) = if on {
+            (tags, Vec::new())
+        } else {
+            (Vec::new(), tags)
+        };
+        self.db.write(Write::Tag {
+            query: set.query(),
+            add,
+            remove,
+        });
+        update_thread(data, &set.thread, |thread| {
+            thread.set_tag(&set.messages, tag, on);
+        });
+    }
+
+    /// Sends the draft `id` in the background, or queues it in the outbox to be sent at
+    /// `send_at`, and closes its window once that's done.
+    fn send_draft(
+        &self,
+        ctx: &mut DelegateCtx,
+        data: &mut MailData,
+        id: u64,
+        send_at: Option<DateTime<Local>>,
+    ) {
+        let identities = data.identities.clone();
+        let draft = match data.drafts.get_mut(&id) {
+            Some(draft) => draft,
+            None => return,
+        };
+        let identity = match identities.get(draft.identity) {
+            Some(identity) => identity.clone(),
+            None => {
+                draft.status = "Choose an identity to send from.".to_string();
+                return;
+            }
+        };
+        if send_at.is_some() && self.outbox.is_none() {
+            draft.status = "Sending later needs an outbox-folder in config.toml.".to_string();
+            return;
+        }
+        let worker = match self.draft_workers.get(&id) {
+            Some(worker) => worker,
+            None => return,
+        };
+        draft.status = "Sending...".to_string();
+        let draft = draft.clone();
+        let db = self.db.clone();
+        let outbox = self.outbox.clone();
+        let window = self.compose_window(id);
+        let event_sink = ctx.get_external_handle();
+        worker.run(move |saved| {
+            // With an outbox everything goes through it, so failures are retried
+            let result = match &outbox {
+                Some(outbox) => {
+                    let send_at = send_at.unwrap_or_else(Local::now);
+                    compose::queue(&draft, &identity, outbox.folder(), &send_at, saved, &db)
+                        .map(|()| outbox.wake())
+                }
+                None => compose::send(&draft, &identity, saved, &db),
+            };
+            let sent = result.is_ok();
+            event_sink.add_idle_callback(move |data: &mut MailData| match result {
+                Ok(()) => {
+                    data.drafts.remove(&id);
+                }
+                Err(e) => {
+                    if let Some(draft) = data.drafts.get_mut(&id) {
+                        draft.status = e;
+                    }
+                }
+            });
+            if let (true, Some(window)) = (sent, window) {
+                let _ = event_sink.submit_command(
+                    druid::commands::CLOSE_WINDOW,
+                    (),
+                    Target::Window(window),
+                );
+            }
+        });
+    }
+
+    /// Takes the queued messages in `thread` out of the outbox straight away, and returns
+    /// each one along with where it was moved to. Those that can't be taken out are
+    /// reported in the status line.
+    fn take_out_queued(&self, data: &mut MailData, thread: &Thread) -> Vec<(Email, PathBuf)> {
+        let outbox = match &self.outbox {
+            Some(outbox) => outbox,
+            None => return Vec::new(),
+        };
+        let mut taken = Vec::new();
+        for email in thread.messages.iter() {
+            if outbox.contains(&email.path) {
+                match outbox.take_out(&email.path) {
+                    Ok(moved) => taken.push((email.clone(), moved)),
+                    Err(e) => data.status = e,
+                }
+            }
+        }
+        taken
+    }
+
+    /// Deletes messages taken out of the outbox in the background, and shows `done` or
+    /// what went wrong in the status line.
+    fn discard_queued(
+        &self,
+        ctx: &mut DelegateCtx,
+        taken: Vec<(Arc<PathBuf>, PathBuf)>,
+        done: Option<&'static str>,
+    ) {
+        let db = self.db.clone();
+        let event_sink = ctx.get_external_handle();
+        thread::spawn(move || {
+            let errors: Vec<String> = taken
+                .iter()
+                .filter_map(|(queued, moved)| outbox::discard(queued, moved, &db).err())
+                .collect();
+            let status = if errors.is_empty() {
+                done.map(str::to_string)
+            } else {
+                Some(errors.join("; "))
+            };
+            if let Some(status) = status {
+                event_sink.add_idle_callback(move |data: &mut MailData| data.status = status);
+            }
+        });
+    }
+
+    fn compose_window(&self, id: u64) -> Option<WindowId> {
+        self.compose_windows
+            .iter()
+            .find(|(_, draft)| **draft == id)
+            .map(|(window, _)| *window)
+    }
 }
 
 impl AppDelegate<MailData> for Delegate {
@@ -60,35 +490,310 @@ impl AppDelegate<MailData> for Delegate {
     ) -> Handled {
         if let Some(query) = cmd.get(SEARCH_CHANGE) {
             data.done_loading = false;
-            let event_sink = ctx.get_external_handle();
-            let query_clone = query.clone();
-            let db_loc = self.database.clone();
-            let _detached_thread =
-                thread::spawn(move || mail::load_mail(query_clone, event_sink, &db_loc));
+            data.searches.selected = query.clone();
+            data.status = String::new();
+            self.db.search(query.clone());
             return Handled::Yes;
         }
 
-        if let Some(mut to_load) = cmd.get(LOAD_THREAD) {
-            let loading_thread = mail::load_thread_from_disk(to_load.clone());
-            to_load = &loading_thread.clone();
-            data.loaded_thread = Some(loading_thread.clone());
+        if let Some(threads) = cmd.get(THREADS_LOADED) {
+            data.threads = threads.clone();
+            data.done_loading = true;
+            self.load_snippets(ctx, threads);
+            if let Some(id) = self.restore_thread.take() {
+                if let Some(thread) = threads.iter().find(|thread| thread.id == id) {
+                    ctx.submit_command(LOAD_THREAD.with(thread.clone()));
+                }
+            }
             return Handled::Yes;
         }
 
-        if let Some(to_mark) = cmd.get(MARK_READ) {
-            let db = Database::open(Path::new(&self.database), DatabaseMode::ReadWrite).unwrap();
-            let nm_messages = db
-                .create_query(&format!("thread:{}", to_mark.id))
-                .unwrap()
-                .search_messages()
-                .unwrap();
-            for message in nm_messages {
-                message.remove_tag("unread").unwrap();
+        if let Some(snippets) = cmd.get(SNIPPETS_LOADED) {
+            for (id, snippet) in snippets {
+                update_thread(data, id, |thread| thread.snippet = snippet.clone());
             }
+            return Handled::Yes;
+        }
+
+        if let Some(show) = cmd.get(SHOW_EXCLUDED) {
+            data.done_loading = false;
+            self.db.show_excluded(*show);
+            self.db.search(data.searches.selected.clone());
+            return Handled::Yes;
+        }
+
+        if let Some(to_load) = cmd.get(LOAD_THREAD) {
+            if data.reading_pane == ReadingPane::Window {
+                // The reading pane isn't on show, so its tabs are left as they are
+                self.open_thread_window(ctx, data, to_load);
+                return Handled::Yes;
+            }
+            if data.tabs.is_open(&to_load.id) {
+                data.tabs.select(&to_load.id);
+                return Handled::Yes;
+            }
+            // Show the thread's summary straight away while its messages are parsed
+            if let Some(replaced) = data.tabs.show(placeholder(to_load)) {
+                self.cancel_load(data, &replaced.id);
+            }
+            self.start_load(ctx, to_load);
+            return Handled::Yes;
+        }
+
+        if let Some(to_open) = cmd.get(OPEN_TAB) {
+            if data.reading_pane == ReadingPane::Window {
+                // Every thread gets a window of its own anyway
+                ctx.submit_command(LOAD_THREAD.with(to_open.clone()));
+            } else if data.tabs.is_open(&to_open.id) {
+                data.tabs.select(&to_open.id);
+            } else {
+                data.tabs.open_new(placeholder(to_open));
+                self.start_load(ctx, to_open);
+            }
+            return Handled::Yes;
+        }
+
+        if let Some(id) = cmd.get(SELECT_TAB) {
+            data.tabs.select(id);
+            return Handled::Yes;
+        }
+
+        if let Some(id) = cmd.get(CLOSE_TAB) {
+            if data.tabs.close(id).is_some() {
+                self.cancel_load(data, id);
+            }
+            return Handled::Yes;
+        }
+
+        if cmd.is(REOPEN_TAB) {
+            if let Some(thread) = data.tabs.take_closed() {
+                if data.tabs.is_open(&thread.id) {
+                    data.tabs.select(&thread.id);
+                } else {
+                    // A tab closed before its thread finished loading needs loading again
+                    if thread.loading {
+                        self.start_load(ctx, &thread);
+                    }
+                    data.tabs.open_new(thread);
+                }
+            }
+            return Handled::Yes;
+        }
+
+        if let Some(loaded) = cmd.get(THREAD_LOADED) {
+            self.loads.remove(&loaded.id);
+            // Drop the result if every tab and window waiting for it has since closed
+            let in_tab = data.tabs.loaded(loaded);
+            let in_window = data
+                .thread_windows
+                .get(&loaded.id)
+                .map_or(false, |open| open.loading);
+            if in_window {
+                data.thread_windows
+                    .insert(loaded.id.clone(), loaded.clone());
+            }
+            if in_tab || in_window {
+                self.open_saved_drafts(ctx, data, loaded);
+            }
+            return Handled::Yes;
+        }
+
+        if let Some(set) = cmd.get(MARK_READ) {
+            self.set_tag(data, set, "unread", false);
+            return Handled::Yes;
+        }
+
+        if let Some(set) = cmd.get(MARK_UNREAD) {
+            self.set_tag(data, set, "unread", true);
+            return Handled::Yes;
+        }
+
+        if let Some(set) = cmd.get(FLAG) {
+            self.set_tag(data, set, "flagged", true);
+            return Handled::Yes;
+        }
+
+        if let Some(set) = cmd.get(UNFLAG) {
+            self.set_tag(data, set, "flagged", false);
+            return Handled::Yes;
+        }
+
+        if let Some(set) = cmd.get(MUTE) {
+            self.set_tag(data, set, "muted", true);
+            return Handled::Yes;
+        }
+
+        if let Some(set) = cmd.get(UNMUTE) {
+            self.set_tag(data, set, "muted", false);
+            return Handled::Yes;
+        }
+
+        if let Some((id, until)) = cmd.get(SNOOZE) {
+            for write in snooze::snooze(id, until) {
+                self.db.write(write);
+            }
+            update_thread(data, id, |thread| {
+                thread.set_tag(&[], "inbox", false);
+                thread.set_tag(&[], "snoozed", true);
+                thread.snoozed_until = Some(Arc::new(*until));
+                thread.choosing_snooze = false;
+                thread.snooze_time.clear();
+            });
+            return Handled::Yes;
+        }
+
+        if let Some(id) = cmd.get(UNSNOOZE) {
+            for write in snooze::unsnooze(id) {
+                self.db.write(write);
+            }
+            update_thread(data, id, |thread| {
+                thread.set_tag(&[], "snoozed", false);
+                thread.set_tag(&[], "inbox", true);
+                thread.snoozed_until = None;
+            });
+            return Handled::Yes;
+        }
+
+        if let Some(path) = cmd.get(VIEW_SOURCE) {
+            let raw = match std::fs::read(&**path) {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => format!("Unable to read {}: {}", path.display(), e),
+            };
+            data.source_views.insert(path.clone(), SourceView::new(raw));
+            let window = WindowDesc::new(theme::themed(
+                &self.themes,
+                ui::source_view::source_window(path.clone()),
+            ))
+            .title(format!("Source of {}", path.display()))
+            .window_size((800.0, 600.0));
+            self.source_windows.insert(window.id, path.clone());
+            ctx.new_window(window);
+            return Handled::Yes;
+        }
+
+        if cmd.is(COMPOSE_NEW) {
+            let draft = Draft::new(&data.identities);
+            self.open_compose(ctx, data, draft);
+            return Handled::Yes;
+        }
+
+        if let Some(email) = cmd.get(REPLY) {
+            let draft = Draft::reply(email, &data.identities);
+            self.open_compose(ctx, data, draft);
+            return Handled::Yes;
+        }
+
+        if let Some(id) = cmd.get(SEND_DRAFT) {
+            self.send_draft(ctx, data, *id, None);
+            return Handled::Yes;
+        }
+
+        if let Some(id) = cmd.get(SEND_LATER) {
+            let now = Local::now();
+            if let Some(draft) = data.drafts.get_mut(id) {
+                match snooze::parse_custom(&draft.send_at, &now) {
+                    Some(send_at) => self.send_draft(ctx, data, *id, Some(send_at)),
+                    None => {
+                        draft.status =
+                            "Enter a time to come, like 2024-03-01 14:30 or 14:30.".to_string()
+                    }
+                }
+            }
+            return Handled::Yes;
+        }
+
+        if let Some(thread) = cmd.get(EDIT_QUEUED) {
+            // Out of the sender's way before the drafts open, so nothing goes meanwhile
+            let taken = self.take_out_queued(data, thread);
+            for (email, _) in &taken {
+                let draft = outbox::to_draft(email, &data.identities);
+                let id = self.open_compose(ctx, data, draft);
+                // Closing the window must save it even if it wasn't touched
+                self.saved_drafts.remove(&id);
+            }
+            let taken = taken
+                .into_iter()
+                .map(|(email, moved)| (email.path, moved))
+                .collect();
+            self.discard_queued(ctx, taken, None);
+            return Handled::Yes;
+        }
+
+        if let Some(thread) = cmd.get(CANCEL_QUEUED) {
+            let taken: Vec<_> = self
+                .take_out_queued(data, thread)
+                .into_iter()
+                .map(|(email, moved)| (email.path, moved))
+                .collect();
+            if !taken.is_empty() {
+                self.discard_queued(ctx, taken, Some("Sending cancelled."));
+            }
+            return Handled::Yes;
+        }
+
+        if let Some(id) = cmd.get(SAVE_DRAFT) {
+            if let Some(draft) = data.drafts.get(id).cloned() {
+                self.save_draft(ctx, data, *id, draft);
+            }
+            return Handled::Yes;
+        }
+
+        if let Some(id) = cmd.get(DISCARD_DRAFT) {
+            // Removing the draft first stops the window closing from saving it again
+            data.drafts.remove(id);
+            if let Some(worker) = self.draft_workers.get(id) {
+                let db = self.db.clone();
+                worker.run(move |saved| {
+                    if let Some(path) = saved.take() {
+                        if let Err(e) = compose::delete_draft(&path, &db) {
+                            eprintln!("{}", e);
+                        }
+                    }
+                });
+            }
+            if let Some(window) = self.compose_window(*id) {
+                ctx.submit_command(druid::commands::CLOSE_WINDOW.to(window));
+            }
+            return Handled::Yes;
         }
 
         Handled::No
     }
+
+    fn window_removed(
+        &mut self,
+        id: WindowId,
+        data: &mut MailData,
+        _env: &Env,
+        ctx: &mut DelegateCtx,
+    ) {
+        if let Some(path) = self.source_windows.remove(&id) {
+            // Only drop the source if no other window is still showing the same message
+            if !self.source_windows.values().any(|other| other == &path) {
+                data.source_views.remove(&path);
+            }
+        }
+        if let Some(thread) = self.thread_windows.remove(&id) {
+            data.thread_windows.remove(&thread);
+            self.cancel_load(data, &thread);
+        }
+        if let Some(id) = self.compose_windows.remove(&id) {
+            // A draft that is still here wasn't sent or discarded, so keep it for later if
+            // there's anything new in it
+            if let Some(draft) = data.drafts.remove(&id) {
+                let edited = self
+                    .saved_drafts
+                    .get(&id)
+                    .map_or(true, |saved| !saved.same_message(&draft));
+                if edited {
+                    self.save_draft(ctx, data, id, draft);
+                }
+            }
+            self.saved_drafts.remove(&id);
+            // Its thread finishes any work still queued, then stops
+            self.draft_workers.remove(&id);
+        }
+    }
 }
 
 fn main() {
@@ -102,109 +807,234 @@ fn main() {
     ));
 
     let config = config_builder.build().expect("Error reading config file");
-    let selected_search = Arc::from("tag:inbox");
+    let themes = Themes::load(&config_dir().unwrap().join("seneca/themes"), &config);
+    let notmuch = NotmuchConfig::load().override_with(&config);
+    let session = SessionState::load();
+    let selected_search: ArcStr = session
+        .search
+        .as_deref()
+        .map(ArcStr::from)
+        .unwrap_or_else(|| Arc::from("tag:inbox"));
 
+    let identities = identity::from_config(&config, &notmuch);
+    *dates::DATE_FORMAT.write().unwrap() = dates::DateFormat::from_config(&config);
+    let outbox_folder = config.get_string("outbox-folder").ok().map(PathBuf::from);
+    let mut search_list = vector![
+        (Arc::from("Inbox"), Arc::from("tag:inbox"), 0),
+        (Arc::from("Flagged"), Arc::from("tag:flagged"), 0),
+        (Arc::from("Snoozed"), Arc::from("tag:snoozed"), 0),
+        (Arc::from("Github"), Arc::from("tag:github"), 0),
+        (Arc::from("Drafts"), Arc::from("tag:draft"), 0)
+    ];
+    if outbox_folder.is_some() {
+        search_list.push_back((Arc::from("Outbox"), Arc::from("tag:outbox"), 0));
+    }
     let search_mail = MailData {
         threads: Vector::new(),
         searches: Searches {
-            search_list: vector![
-                (Arc::from("Inbox"), Arc::clone(&selected_search)),
-                (Arc::from("Github"), Arc::from("tag:github"))
-            ],
-            selected: selected_search,
+            search_list,
+            selected: selected_search.clone(),
         },
+        query: String::new(),
+        status: String::new(),
         done_loading: false,
-        loaded_thread: None,
+        show_excluded: false,
+        tabs: Tabs::default(),
+        source_views: im::HashMap::new(),
+        identities: identities.clone(),
+        drafts: im::HashMap::new(),
+        theme: session
+            .theme
+            .clone()
+            .or_else(|| config.get_string("theme").ok())
+            .map(ArcStr::from)
+            .unwrap_or_else(|| ArcStr::from("light")),
+        theme_names: themes.names(),
+        zoom: 1.0,
+        sidebar_split: session.sidebar_split,
+        list_split: session.list_split,
+        bottom_split: session.bottom_split,
+        reading_pane: config
+            .get_string("reading-pane")
+            .ok()
+            .and_then(|name| ReadingPane::from_name(&name))
+            .unwrap_or_default(),
+        thread_windows: im::HashMap::new(),
     };
 
-    let main_window = WindowDesc::new(root_widget())
+    let tag_styles = Arc::new(TagStyles::from_config(&config));
+    let mark_read = config
+        .get_string("mark-read")
+        .ok()
+        .and_then(|name| MarkRead::from_name(&name))
+        .unwrap_or_default();
+    let root = root_widget(tag_styles, identities, mark_read);
+    let mut main_window = WindowDesc::new(theme::themed(&themes, root))
         .title("Seneca")
-        .window_size((1000.0, 500.0));
+        .window_size(session.window_size);
+    if let Some(position) = session.window_position {
+        main_window = main_window.set_position(position);
+    }
 
-    let db_osstr: OsString = config
-        .get_string("db-location")
-        .expect("No notmuch database in config file.")
-        .to_string()
-        .into();
+    // SENECA_FIXTURES points at a directory of .eml files to browse instead of the
+    // notmuch database, which is handy for trying out the UI
+    let exclude_tags = notmuch.exclude_tags.clone();
+    let fixtures = std::env::var_os("SENECA_FIXTURES");
+    // Called once for the database service and once for the address book, which each
+    // keep their own handle
+    let open_store: Arc<dyn Fn() -> Box<dyn MailStore> + Send + Sync> = match fixtures {
+        Some(dir) => Arc::new(move || {
+            let store = MemoryStore::from_dir(Path::new(&dir));
+            Box::new(store.with_exclude_tags(exclude_tags.clone())) as Box<dyn MailStore>
+        }),
+        None => {
+            let location: OsString = notmuch
+                .database_path
+                .clone()
+                .expect("No notmuch database: set database.path or db-location in config.toml.")
+                .into();
+            Arc::new(move || {
+                Box::new(NotmuchStore::new(location.clone(), exclude_tags.clone()))
+                    as Box<dyn MailStore>
+            })
+        }
+    };
 
-    let launcher = AppLauncher::with_window(main_window);
-    let event_sink = launcher.get_external_handle();
-    let db_clone = db_osstr.clone();
+    if let Ok(vcard_files) = config.get_array("vcard-files") {
+        let mut address_book = contacts::ADDRESS_BOOK.write().unwrap();
+        for file in vcard_files {
+            let path = file
+                .into_str()
+                .expect("vcard-files must be a list of paths");
+            if let Err(e) = address_book.import_vcard(Path::new(&path)) {
+                eprintln!("Unable to import contacts from {}: {}", path, e);
+            }
+        }
+    }
 
-    thread::spawn(move || mail::load_mail(Arc::from("tag:inbox"), event_sink, &db_clone));
+    let launcher = AppLauncher::with_window(main_window);
+    let contacts = contacts::ScannerHandle::spawn({
+        let open_store = open_store.clone();
+        move || open_store()
+    });
+    let own_addresses = search_mail
+        .identities
+        .iter()
+        .map(|identity| identity.address.clone())
+        .collect();
+    let db = DbHandle::spawn(
+        move || open_store(),
+        own_addresses,
+        contacts,
+        launcher.get_external_handle(),
+    );
+    db.search(selected_search);
+    db.count_unread(
+        search_mail
+            .searches
+            .search_list
+            .iter()
+            .map(|(_, query, _)| query.clone())
+            .collect(),
+    );
+    let outbox = outbox_folder.map(|folder| {
+        OutboxHandle::spawn(
+            folder,
+            search_mail.identities.clone(),
+            db.clone(),
+            launcher.get_external_handle(),
+        )
+    });
 
     launcher
         .log_to_console()
-        .delegate(Delegate { database: db_osstr })
+        .delegate(Delegate {
+            db,
+            source_windows: HashMap::new(),
+            thread_windows: HashMap::new(),
+            compose_windows: HashMap::new(),
+            next_draft: 0,
+            draft_workers: HashMap::new(),
+            saved_drafts: HashMap::new(),
+            drafts_folder: config.get_string("drafts-folder").ok().map(PathBuf::from),
+            outbox,
+            message_cache: Arc::new(Mutex::new(MessageCache::default())),
+            snippets: Arc::new(Mutex::new(HashMap::new())),
+            snippet_generation: Arc::new(AtomicU64::new(0)),
+            loads: HashMap::new(),
+            themes,
+            mark_read,
+            restore_thread: session.thread,
+        })
         .configure_env(move |env: &mut Env, _app: &MailData| {
-            env.set(
-                THREAD_BACKGROUND_COLOR,
-                get_color_from_config("thread-background-color", &config),
-            );
-            env.set(
-                THREAD_SELECTED_COLOR,
-                get_color_from_config("thread-selected-color", &config),
-            );
-            env.set(BORDER_COLOR, get_color_from_config("border-color", &config));
-            env.set(
-                SEARCH_BACKGROUND_COLOR,
-                get_color_from_config("search-background-color", &config),
-            );
-            env.set(
-                SEARCH_SELECTED_COLOR,
-                get_color_from_config("search-selected-color", &config),
-            );
-            env.set(
-                UI_FONT,
-                FontDescriptor::new(FontFamily::SYSTEM_UI).with_size(13.0),
-            );
-            env.set(
-                UI_FONT_LARGE,
-                FontDescriptor::new(FontFamily::SYSTEM_UI).with_size(22.0),
-            );
-            env.set(
-                UI_FONT_LIGHT,
-                FontDescriptor::new(FontFamily::SYSTEM_UI)
-                    .with_size(13.0)
-                    .with_weight(FontWeight::LIGHT),
-            );
-            env.set(druid::theme::TEXT_COLOR, Color::BLACK);
             env.set(druid::theme::WIDGET_PADDING_HORIZONTAL, 1.);
         })
         .launch(search_mail)
         .expect("Failed to launch Seneca");
 }
 
-fn get_color_from_config(key: &str, config: &Config) -> Color {
-    let color_table = config.get_table(key).expect(&format!(
-        "No color theme found in config file. Missing key: {}.",
-        key
-    ));
-    Color::rgb8(
-        color_table.get("r").unwrap().clone().into_int().unwrap() as u8,
-        color_table.get("g").unwrap().clone().into_int().unwrap() as u8,
-        color_table.get("b").unwrap().clone().into_int().unwrap() as u8,
-    )
-}
-
-fn root_widget() -> impl Widget<MailData> {
+fn root_widget(
+    tag_styles: Arc<TagStyles>,
+    identities: Vector<Identity>,
+    mark_read: MarkRead,
+) -> impl Widget<MailData> {
     let search_sidebar = ui::search_list::search_sidebar();
-    let thread_widget = ui::thread_list::thread_list();
-    let loading_widget = Label::new("Loading...").center();
+    let threads = move || {
+        Either::new(
+            |data: &MailData, _env| data.done_loading,
+            ui::thread_list::thread_list(tag_styles.clone(), identities.clone()),
+            Label::new("Loading...").center(),
+        )
+    };
+    let open_thread = move || ui::tabs::reading_pane(mark_read).lens(MailData::tabs);
 
     Split::columns(
-        Container::new(Scroll::new(search_sidebar).vertical().background(SEARCH_BACKGROUND_COLOR)),
-        Split::columns(
-            Either::new(
-                |data, _env| data.done_loading,
-                thread_widget,
-                loading_widget,
-            ),
-            Maybe::or_empty(|| mail::mail_layout()).lens(MailData::loaded_thread),
-        )
-        .split_point(0.3)
-        .bar_size(0.0)
+        MailData::sidebar_split,
+        Container::new(
+            Scroll::new(search_sidebar)
+                .vertical()
+                .background(SEARCH_BACKGROUND_COLOR),
+        ),
+        ViewSwitcher::new(
+            |data: &MailData, _env| data.reading_pane,
+            move |pane, _data, _env| -> Box<dyn Widget<MailData>> {
+                match pane {
+                    ReadingPane::Right => Box::new(Split::columns(
+                        MailData::list_split,
+                        threads(),
+                        open_thread(),
+                    )),
+                    ReadingPane::Bottom => Box::new(Split::rows(
+                        MailData::bottom_split,
+                        threads(),
+                        open_thread(),
+                    )),
+                    ReadingPane::Window => Box::new(threads()),
+                }
+            },
+        ),
     )
-    .split_point(0.15)
-    .bar_size(0.0)
+    .controller(SaveSession)
+    .controller(TabShortcuts)
+}
+
+/// A copy of `thread` to show while its messages are being parsed.
+fn placeholder(thread: &Thread) -> Arc<Thread> {
+    let mut placeholder = thread.clone();
+    placeholder.loading = true;
+    Arc::new(placeholder)
+}
+
+/// Applies `change` to every copy of the thread `id`: in the thread list, in tabs open
+/// or closed, and in windows.
+fn update_thread(data: &mut MailData, id: &str, change: impl Fn(&mut Thread)) {
+    let threads = data
+        .threads
+        .iter_mut()
+        .chain(data.tabs.open.iter_mut())
+        .chain(data.tabs.closed.iter_mut())
+        .chain(data.thread_windows.iter_mut().map(|(_, thread)| thread));
+    for thread in threads.filter(|thread| thread.id == id) {
+        change(Arc::make_mut(thread));
+    }
 }
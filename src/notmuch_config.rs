@@ -0,0 +1,129 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use config::{Config, File, FileFormat};
+use dirs::{config_dir, home_dir};
+
+/// Tags notmuch gives new mail when `new.tags` isn't set.
+const DEFAULT_NEW_TAGS: &[&str] = &["unread", "inbox"];
+
+/// The parts of notmuch's own configuration Seneca uses, so that `config.toml` doesn't
+/// have to repeat them. Anything set in `config.toml` takes precedence.
+#[derive(Debug, Default)]
+pub struct NotmuchConfig {
+    pub database_path: Option<PathBuf>,
+    pub user_name: Option<String>,
+    pub primary_email: Option<String>,
+    pub other_email: Vec<String>,
+    pub new_tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+}
+
+impl NotmuchConfig {
+    /// Reads the notmuch configuration from the first of `$NOTMUCH_CONFIG`,
+    /// `$XDG_CONFIG_HOME/notmuch/default/config` and `~/.notmuch-config` that exists.
+    /// Without one, notmuch's defaults are used.
+    pub fn load() -> Self {
+        let path = match config_file() {
+            Some(path) => path,
+            None => return Self::defaults(None),
+        };
+        let config = Config::builder()
+            .add_source(File::from(path.as_path()).format(FileFormat::Ini))
+            .build();
+        match config {
+            Ok(config) => Self::defaults(Some(&config)),
+            Err(e) => {
+                eprintln!("Unable to read {}: {}", path.display(), e);
+                Self::defaults(None)
+            }
+        }
+    }
+
+    fn defaults(config: Option<&Config>) -> Self {
+        let get = |key: &str| config.and_then(|config| config.get_string(key).ok());
+        let new_tags = get("new.tags")
+            .map(|tags| split_list(&tags))
+            .unwrap_or_else(|| DEFAULT_NEW_TAGS.iter().map(|tag| tag.to_string()).collect());
+        Self {
+            database_path: get("database.path")
+                .map(|path| relative_to_home(Path::new(&path)))
+                .or_else(default_database_path),
+            user_name: get("user.name").filter(|name| !name.is_empty()),
+            primary_email: get("user.primary_email").filter(|email| !email.is_empty()),
+            other_email: get("user.other_email")
+                .map(|emails| split_list(&emails))
+                .unwrap_or_default(),
+            new_tags,
+            exclude_tags: get("search.exclude_tags")
+                .map(|tags| split_list(&tags))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Replaces notmuch's settings with any set in `config.toml`: `db-location`,
+    /// `new-tags` and `exclude-tags`.
+    pub fn override_with(mut self, config: &Config) -> Self {
+        let list = |key: &str| -> Option<Vec<String>> {
+            let values = config.get_array(key).ok()?;
+            Some(
+                values
+                    .into_iter()
+                    .filter_map(|value| value.into_str().ok())
+                    .collect(),
+            )
+        };
+        if let Ok(location) = config.get_string("db-location") {
+            self.database_path = Some(PathBuf::from(location));
+        }
+        if let Some(tags) = list("new-tags") {
+            self.new_tags = tags;
+        }
+        if let Some(tags) = list("exclude-tags") {
+            self.exclude_tags = tags;
+        }
+        self
+    }
+
+    /// Every address configured for the user, primary first.
+    pub fn emails(&self) -> impl Iterator<Item = &String> {
+        self.primary_email.iter().chain(self.other_email.iter())
+    }
+}
+
+fn config_file() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("NOTMUCH_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let xdg = config_dir().map(|dir| dir.join("notmuch/default/config"));
+    let home = home_dir().map(|dir| dir.join(".notmuch-config"));
+    xdg.into_iter().chain(home).find(|path| path.is_file())
+}
+
+/// notmuch lists are separated by semicolons, usually with a trailing one.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// notmuch resolves a relative `database.path` against the home directory.
+fn relative_to_home(path: &Path) -> PathBuf {
+    match home_dir() {
+        Some(home) if path.is_relative() => home.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Where notmuch looks for mail when `database.path` isn't set: `$MAIL`, then `~/mail`,
+/// whichever exists first. Neither existing means there is no database to open.
+fn default_database_path() -> Option<PathBuf> {
+    env::var_os("MAIL")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(home_dir().map(|home| home.join("mail")))
+        .find(|path| path.is_dir())
+}
@@ -0,0 +1,645 @@
+//! The outbox: messages waiting in a maildir to be sent at a chosen time, and the
+//! thread that sends them. Each message carries its send time and follow-up request in
+//! headers of Seneca's own, which are taken off before it goes out. Sending is retried
+//! with backoff, so mail written while offline goes out once the transport works again;
+//! the number of attempts is kept in a header too, so giving up survives a restart.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use druid::im::Vector;
+use druid::ExtEventSink;
+use mailparse::{parse_headers, MailHeaderMap};
+
+use crate::compose::{self, Draft};
+use crate::db::DbHandle;
+use crate::identity::Identity;
+use crate::mail::{Email, MessageSet};
+use crate::store::Write;
+use crate::{address, maildir, transport, MailData};
+
+const SEND_AT_HEADER: &str = "X-Seneca-Send-At";
+const FOLLOW_UP_HEADER: &str = "X-Seneca-Follow-Up";
+const ATTEMPTS_HEADER: &str = "X-Seneca-Attempts";
+/// The tag of every message in the outbox.
+pub const OUTBOX_TAG: &str = "outbox";
+/// Added to outbox messages that couldn't be sent after every attempt.
+const FAILED_TAG: &str = "failed";
+const INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+const MAX_SEND_ATTEMPTS: u32 = 10;
+/// The longest the sender sleeps before looking at the outbox again.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// A message read back from the outbox.
+struct Queued {
+    send_at: DateTime<Local>,
+    follow_up: Option<u32>,
+    /// How many times sending it has failed
+    attempts: u32,
+    /// The message as it is sent, without Seneca's headers
+    message: String,
+    /// Without the angle brackets
+    message_id: String,
+    from: String,
+}
+
+impl Queued {
+    fn read(path: &Path) -> Result<Self, String> {
+        let raw = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let mut send_at = None;
+        let mut follow_up = None;
+        let mut attempts = 0;
+        let mut message = String::with_capacity(raw.len());
+        let mut in_headers = true;
+        for line in raw.split_inclusive('\n') {
+            if line.trim_end().is_empty() {
+                in_headers = false;
+            }
+            if in_headers {
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case(SEND_AT_HEADER) {
+                        send_at = DateTime::parse_from_rfc3339(value.trim()).ok();
+                        continue;
+                    }
+                    if name.eq_ignore_ascii_case(FOLLOW_UP_HEADER) {
+                        follow_up = value.trim().parse().ok();
+                        continue;
+                    }
+                    if name.eq_ignore_ascii_case(ATTEMPTS_HEADER) {
+                        attempts = value.trim().parse().unwrap_or_default();
+                        continue;
+                    }
+                }
+            }
+            message.push_str(line);
+        }
+        let (headers, _) = parse_headers(message.as_bytes())
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let message_id = headers
+            .get_first_value("Message-ID")
+            .unwrap_or_default()
+            .trim()
+            .trim_start_matches('<')
+            .trim_end_matches('>')
+            .to_string();
+        let from = headers.get_first_value("From").unwrap_or_default();
+        Ok(Self {
+            // Messages without a send time are sent straight away
+            send_at: send_at.map_or_else(Local::now, |at| at.with_timezone(&Local)),
+            follow_up,
+            attempts,
+            message,
+            message_id,
+            from,
+        })
+    }
+
+    /// A query for the message, which finds the sent copy once it has gone.
+    fn query(&self) -> String {
+        MessageSet {
+            thread: String::new(),
+            messages: vec![self.message_id.clone()],
+        }
+        .query()
+    }
+}
+
+/// `message` with Seneca's headers for its send time and follow-up added.
+fn with_headers(message: &str, send_at: &DateTime<Local>, follow_up: Option<u32>) -> String {
+    let mut queued = format!("{}: {}\n", SEND_AT_HEADER, send_at.to_rfc3339());
+    if let Some(days) = follow_up {
+        queued.push_str(&format!("{}: {}\n", FOLLOW_UP_HEADER, days));
+    }
+    queued.push_str(message);
+    queued
+}
+
+/// Rewrites the message at `path` in the outbox at `folder` with the number of times it
+/// has failed to send. The new copy is written to `tmp` and renamed over the old one.
+fn record_attempts(folder: &Path, path: &Path, attempts: u32) -> Result<(), String> {
+    let raw = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let mut rewritten = format!("{}: {}\n", ATTEMPTS_HEADER, attempts);
+    let mut in_headers = true;
+    for line in raw.split_inclusive('\n') {
+        if line.trim_end().is_empty() {
+            in_headers = false;
+        }
+        let old_count = in_headers
+            && line.split_once(':').map_or(false, |(name, _)| {
+                name.eq_ignore_ascii_case(ATTEMPTS_HEADER)
+            });
+        if !old_count {
+            rewritten.push_str(line);
+        }
+    }
+    let tmp = folder.join("tmp").join(maildir::unique_name());
+    fs::create_dir_all(folder.join("tmp"))
+        .and_then(|()| fs::write(&tmp, rewritten))
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("Unable to update {}: {}", path.display(), e))
+}
+
+/// How long to wait after a message has failed to send `attempts` times.
+fn backoff(attempts: u32) -> Duration {
+    (INITIAL_BACKOFF * 2u32.pow(attempts - 1)).min(MAX_BACKOFF)
+}
+
+/// How long until `queued` can be sent, given it mustn't be tried again before
+/// `retry_at`; zero if it can go now.
+fn wait_for(queued: &Queued, retry_at: Instant) -> Duration {
+    let due = (queued.send_at - Local::now()).to_std().unwrap_or_default();
+    due.max(retry_at.saturating_duration_since(Instant::now()))
+}
+
+/// Every message in the outbox at `folder`.
+fn queued(folder: &Path) -> Vec<PathBuf> {
+    ["new", "cur"]
+        .iter()
+        .filter_map(|subdir| fs::read_dir(folder.join(subdir)).ok())
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Sends the message at `path` in the outbox at `folder` through `identity`'s
+/// transport. The file is moved to the outbox's `tmp` folder first, which is neither
+/// sent from nor indexed, so it can't go twice whatever happens once it has gone; it's
+/// only put back if the transport fails, or by `recover` if Seneca stopped meanwhile.
+/// Returns where the file was moved to.
+fn transmit(
+    folder: &Path,
+    path: &Path,
+    identity: &Identity,
+    message: &[u8],
+) -> Result<PathBuf, String> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a message", path.display()))?;
+    let sending = folder.join("tmp").join(name);
+    fs::create_dir_all(folder.join("tmp"))
+        .and_then(|()| fs::rename(path, &sending))
+        .map_err(|e| format!("Unable to take {} out of the outbox: {}", path.display(), e))?;
+    if let Err(e) = transport::send(identity, message) {
+        return match fs::rename(&sending, path) {
+            Ok(()) => Err(e),
+            Err(put_back) => Err(format!(
+                "{}, and unable to put {} back in the outbox: {}",
+                e,
+                sending.display(),
+                put_back
+            )),
+        };
+    }
+    Ok(sending)
+}
+
+/// Moves the messages `transmit` had taken out of the outbox at `folder` back in, for
+/// when Seneca stopped while sending them. Other files in `tmp` are copies still being
+/// written, which have no flags in their names and are left alone. Returns where each
+/// message was put back.
+fn recover(folder: &Path) -> Vec<Result<PathBuf, String>> {
+    let entries = match fs::read_dir(folder.join("tmp")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().contains(":2,"))
+        .map(|entry| {
+            let path = entry.path();
+            let back = folder.join("cur").join(entry.file_name());
+            fs::rename(&path, &back)
+                .map(|()| back)
+                .map_err(|e| format!("Unable to put {} back in the outbox: {}", path.display(), e))
+        })
+        .collect()
+}
+
+/// Writes `message` into the outbox at `folder` to be sent at `send_at`, and indexes it
+/// tagged `outbox`.
+pub fn queue(
+    folder: &Path,
+    message: &str,
+    send_at: &DateTime<Local>,
+    follow_up: Option<u32>,
+    db: &DbHandle,
+) -> Result<(), String> {
+    let queued = with_headers(message, send_at, follow_up);
+    let path = maildir::deliver(folder, "S", queued.as_bytes())
+        .map_err(|e| format!("Unable to queue message in {}: {}", folder.display(), e))?;
+    db.write_and_wait(Write::Index {
+        path,
+        tags: vec![OUTBOX_TAG.to_string()],
+    })
+}
+
+/// Deletes the message queued at `queued`, which `OutboxHandle::take_out` moved to
+/// `moved`, and takes it out of the index.
+pub fn discard(queued: &Path, moved: &Path, db: &DbHandle) -> Result<(), String> {
+    fs::remove_file(moved).map_err(|e| format!("Unable to delete {}: {}", moved.display(), e))?;
+    db.write_and_wait(Write::Remove {
+        path: queued.to_path_buf(),
+    })
+}
+
+/// A draft for editing the queued message `email`, with its send time and follow-up.
+pub fn to_draft(email: &Email, identities: &Vector<Identity>) -> Draft {
+    let mut draft = Draft::from_saved(email, identities);
+    // The queued copy goes, and the draft is saved afresh
+    draft.path = None;
+    draft.follow_up = email
+        .header(FOLLOW_UP_HEADER)
+        .and_then(|days| days.trim().parse().ok());
+    draft.send_at = email
+        .header(SEND_AT_HEADER)
+        .and_then(|at| DateTime::parse_from_rfc3339(at.trim()).ok())
+        .map(|at| {
+            at.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default();
+    draft
+}
+
+/// A handle to the thread sending the outbox.
+#[derive(Clone)]
+pub struct OutboxHandle {
+    folder: PathBuf,
+    wake: Sender<()>,
+}
+
+impl OutboxHandle {
+    /// Starts sending what's in the outbox at `folder` as it falls due.
+    pub fn spawn(
+        folder: PathBuf,
+        identities: Vector<Identity>,
+        db: DbHandle,
+        event_sink: ExtEventSink,
+    ) -> Self {
+        let (wake, receiver) = mpsc::channel();
+        let sender = OutboxSender {
+            folder: folder.clone(),
+            identities,
+            db,
+            event_sink,
+            failures: HashMap::new(),
+        };
+        thread::spawn(move || sender.run(receiver));
+        Self { folder, wake }
+    }
+
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    /// Whether the message at `path` is waiting in the outbox.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.folder)
+    }
+
+    /// Moves the message at `path` out of the sender's view straight away, so it can't
+    /// go while it's being edited or cancelled. Fails if it's already being sent.
+    /// Returns where it was moved to.
+    pub fn take_out(&self, path: &Path) -> Result<PathBuf, String> {
+        let moved = self.folder.join("tmp").join(maildir::unique_name());
+        fs::create_dir_all(self.folder.join("tmp"))
+            .and_then(|()| fs::rename(path, &moved))
+            .map(|()| moved)
+            .map_err(|e| {
+                format!(
+                    "Unable to take {} out of the outbox, it may have been sent: {}",
+                    path.display(),
+                    e
+                )
+            })
+    }
+
+    /// Has the outbox looked at again, after a message has been queued.
+    pub fn wake(&self) {
+        let _ = self.wake.send(());
+    }
+}
+
+struct OutboxSender {
+    folder: PathBuf,
+    identities: Vector<Identity>,
+    db: DbHandle,
+    event_sink: ExtEventSink,
+    /// When to try each message that has failed to send next
+    failures: HashMap<PathBuf, Instant>,
+}
+
+impl OutboxSender {
+    fn run(mut self, wake: Receiver<()>) {
+        self.recover();
+        loop {
+            let wait = self.send_due().min(MAX_WAIT);
+            match wake.recv_timeout(wait) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    fn report(&self, message: String) {
+        eprintln!("{}", message);
+        self.event_sink
+            .add_idle_callback(move |data: &mut MailData| data.status = message);
+    }
+
+    /// Puts back messages that were being sent when Seneca last stopped. Whether they
+    /// went is unknown, so rather than chance sending them twice they're given up on,
+    /// for the user to edit and send again or cancel.
+    fn recover(&self) {
+        for recovered in recover(&self.folder) {
+            let result = recovered.and_then(|path| {
+                record_attempts(&self.folder, &path, MAX_SEND_ATTEMPTS)?;
+                let queued = Queued::read(&path)?;
+                self.db.write(Write::Tag {
+                    query: queued.query(),
+                    add: vec![FAILED_TAG.to_string()],
+                    remove: Vec::new(),
+                });
+                Ok(format!(
+                    "{} was being sent when Seneca stopped and may have gone: check, then \
+                     edit it to send again or cancel it",
+                    path.display()
+                ))
+            });
+            match result {
+                Ok(message) | Err(message) => self.report(message),
+            }
+        }
+    }
+
+    /// Sends every message that's due, and returns how long until the next one is.
+    fn send_due(&mut self) -> Duration {
+        let paths = queued(&self.folder);
+        // Forget messages that have been sent, cancelled or taken out to edit
+        self.failures.retain(|path, _| paths.contains(path));
+
+        let mut wait = MAX_WAIT;
+        for path in paths {
+            let queued = match Queued::read(&path) {
+                Ok(queued) => queued,
+                Err(e) => {
+                    self.report(e);
+                    continue;
+                }
+            };
+            if queued.attempts >= MAX_SEND_ATTEMPTS {
+                // Left for the user to edit or cancel
+                continue;
+            }
+            let retry_at = self
+                .failures
+                .get(&path)
+                .copied()
+                .unwrap_or_else(Instant::now);
+            let until = wait_for(&queued, retry_at);
+            if until > Duration::from_secs(0) {
+                wait = wait.min(until);
+                continue;
+            }
+
+            match self.send(&path, &queued) {
+                Ok(()) => {
+                    self.failures.remove(&path);
+                }
+                Err(e) => {
+                    let attempts = queued.attempts + 1;
+                    if let Err(e) = record_attempts(&self.folder, &path, attempts) {
+                        self.report(e);
+                    }
+                    if attempts < MAX_SEND_ATTEMPTS {
+                        let backoff = backoff(attempts);
+                        self.report(format!("{}; retrying in {:?}", e, backoff));
+                        self.failures.insert(path, Instant::now() + backoff);
+                        wait = wait.min(backoff);
+                    } else {
+                        self.report(format!("Gave up sending: {}", e));
+                        self.db.write(Write::Tag {
+                            query: queued.query(),
+                            add: vec![FAILED_TAG.to_string()],
+                            remove: Vec::new(),
+                        });
+                    }
+                }
+            }
+        }
+        wait
+    }
+
+    /// Sends a queued message through its sender's transport and takes it out of the
+    /// outbox. Only failures to send are returned, since those are worth another try;
+    /// trouble tidying up once the message has gone is just reported.
+    fn send(&self, path: &Path, queued: &Queued) -> Result<(), String> {
+        let from = address::parse_value(&queued.from);
+        let identity = self
+            .identities
+            .iter()
+            .find(|identity| {
+                from.iter()
+                    .any(|from| from.email.eq_ignore_ascii_case(&identity.address))
+            })
+            .ok_or_else(|| format!("No identity to send {} from", path.display()))?;
+        let sent = transmit(&self.folder, path, identity, queued.message.as_bytes())?;
+
+        if let Err(e) = fs::remove_file(&sent) {
+            self.report(format!("Unable to delete {}: {}", sent.display(), e));
+        }
+        self.db.write(Write::Remove {
+            path: path.to_path_buf(),
+        });
+        let filed = compose::file_sent(
+            identity,
+            queued.message.as_bytes(),
+            &queued.message_id,
+            queued.follow_up,
+            &self.db,
+        );
+        if let Err(e) = filed {
+            self.report(e);
+        }
+        // The sent copy is the same message, and may have picked up the outbox's tags
+        self.db.write(Write::Tag {
+            query: queued.query(),
+            add: Vec::new(),
+            remove: vec![OUTBOX_TAG.to_string(), FAILED_TAG.to_string()],
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::{Arc, Mutex};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::cache::MessageCache;
+    use crate::mail;
+    use crate::store::{MailStore, MemoryStore};
+    use crate::testing::TempDir;
+
+    const MESSAGE: &str = "From: Ada Lovelace <ada@example.com>\n\
+                           To: Grace Hopper <grace@example.com>\n\
+                           Subject: Notes\n\
+                           Message-ID: <notes@example.com>\n\
+                           \n\
+                           The notes are attached.\n";
+
+    /// Puts `message` in the outbox at `folder` the way `queue` does, without indexing it.
+    fn enqueue(folder: &Path, send_at: &DateTime<Local>) -> PathBuf {
+        let queued = with_headers(MESSAGE, send_at, Some(3));
+        maildir::deliver(folder, "S", queued.as_bytes()).unwrap()
+    }
+
+    /// An identity whose transport runs `script` with the path of a file to write to.
+    fn identity(dir: &Path, script: &str) -> (Identity, PathBuf) {
+        let path = dir.join("transport.sh");
+        fs::write(&path, script).unwrap();
+        let sent = dir.join("sent.eml");
+        let identity = Identity {
+            name: "Ada Lovelace".to_string(),
+            address: "ada@example.com".to_string(),
+            signature: String::new(),
+            transport: format!("sh {} {}", path.display(), sent.display()),
+            sent_folder: None,
+            sent_tags: Vector::new(),
+            default: true,
+        };
+        (identity, sent)
+    }
+
+    #[test]
+    fn queued_messages_keep_their_send_time_and_follow_up() {
+        let outbox = TempDir::new("queue");
+        let send_at = Local::now() + chrono::Duration::hours(2);
+        let path = enqueue(&outbox, &send_at);
+        assert_eq!(queued(&outbox), [path.clone()]);
+
+        let queued = Queued::read(&path).unwrap();
+        assert_eq!(queued.send_at.timestamp(), send_at.timestamp());
+        assert_eq!(queued.follow_up, Some(3));
+        assert_eq!(queued.attempts, 0);
+        assert_eq!(queued.message, MESSAGE);
+        assert_eq!(queued.message_id, "notes@example.com");
+        assert_eq!(queued.from, "Ada Lovelace <ada@example.com>");
+    }
+
+    #[test]
+    fn edited_messages_keep_their_send_time_and_follow_up() {
+        let outbox = TempDir::new("edit");
+        enqueue(&outbox, &Local.ymd(2030, 1, 2).and_hms(9, 30, 0));
+        let mut store = MemoryStore::from_dir(&outbox);
+        let thread = store.query_threads("*").unwrap().remove(0);
+        let thread = mail::load_thread(
+            Arc::new(thread),
+            &*store.source(),
+            &Mutex::new(MessageCache::default()),
+            &AtomicU64::new(0),
+            0,
+        )
+        .unwrap();
+
+        let draft = to_draft(&thread.messages[0], &Vector::new());
+        assert_eq!(draft.send_at, "2030-01-02 09:30");
+        assert_eq!(draft.follow_up, Some(3));
+        assert_eq!(draft.subject, "Notes");
+        assert!(draft.path.is_none());
+    }
+
+    #[test]
+    fn messages_wait_for_their_send_time() {
+        let outbox = TempDir::new("send-later");
+        let in_an_hour = Local::now() + chrono::Duration::hours(1);
+        let later = Queued::read(&enqueue(&outbox, &in_an_hour)).unwrap();
+        let wait = wait_for(&later, Instant::now());
+        assert!(wait > Duration::from_secs(59 * 60) && wait <= Duration::from_secs(60 * 60));
+
+        let now = Queued::read(&enqueue(&outbox, &Local::now())).unwrap();
+        assert_eq!(wait_for(&now, Instant::now()), Duration::from_secs(0));
+        let retry_at = Instant::now() + Duration::from_secs(30);
+        assert!(wait_for(&now, retry_at) > Duration::from_secs(29));
+    }
+
+    #[test]
+    fn retries_back_off_up_to_a_limit() {
+        assert_eq!(backoff(1), INITIAL_BACKOFF);
+        assert_eq!(backoff(2), INITIAL_BACKOFF * 2);
+        assert_eq!(backoff(3), INITIAL_BACKOFF * 4);
+        assert_eq!(backoff(MAX_SEND_ATTEMPTS), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn failed_attempts_are_kept_in_the_message() {
+        let outbox = TempDir::new("attempts");
+        let path = enqueue(&outbox, &Local::now());
+        record_attempts(&outbox, &path, 1).unwrap();
+        record_attempts(&outbox, &path, 2).unwrap();
+
+        let queued_message = Queued::read(&path).unwrap();
+        assert_eq!(queued_message.attempts, 2);
+        assert_eq!(queued_message.follow_up, Some(3));
+        assert_eq!(queued_message.message, MESSAGE);
+        assert_eq!(queued(&outbox), [path]);
+    }
+
+    #[test]
+    fn messages_being_sent_are_put_back() {
+        let outbox = TempDir::new("recover");
+        let path = enqueue(&outbox, &Local::now());
+        let sending = outbox.join("tmp").join(path.file_name().unwrap());
+        fs::rename(&path, &sending).unwrap();
+        let partial = outbox.join("tmp").join(maildir::unique_name());
+        fs::write(&partial, "From: ").unwrap();
+
+        let recovered: Vec<PathBuf> = recover(&outbox).into_iter().flatten().collect();
+        assert_eq!(recovered, [path.clone()]);
+        assert_eq!(queued(&outbox), [path]);
+        assert!(partial.exists());
+    }
+
+    #[test]
+    fn failed_sends_stay_in_the_outbox() {
+        let outbox = TempDir::new("send-failed");
+        let (identity, sent) = identity(
+            &outbox,
+            "cat > /dev/null\necho 'Connection refused' >&2\nexit 75\n",
+        );
+        let path = enqueue(&outbox, &Local::now());
+        let queued_message = Queued::read(&path).unwrap();
+
+        let error = transmit(&outbox, &path, &identity, queued_message.message.as_bytes());
+        assert!(error.unwrap_err().contains("Connection refused"));
+        assert_eq!(queued(&outbox), [path]);
+        assert!(!sent.exists());
+    }
+
+    #[test]
+    fn sent_messages_leave_the_outbox() {
+        let outbox = TempDir::new("send");
+        let (identity, sent) = identity(&outbox, "cat > \"$1\"\n");
+        let path = enqueue(&outbox, &Local::now());
+        let queued_message = Queued::read(&path).unwrap();
+
+        let moved = transmit(&outbox, &path, &identity, queued_message.message.as_bytes());
+        assert!(moved.unwrap().starts_with(outbox.join("tmp")));
+        assert!(queued(&outbox).is_empty());
+        assert_eq!(fs::read_to_string(sent).unwrap(), MESSAGE);
+    }
+}
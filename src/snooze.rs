@@ -0,0 +1,261 @@
+//! Snoozing threads out of the inbox until a wake time. The wake time is kept in a
+//! message property, so it survives restarts and other notmuch clients can see it.
+
+use std::collections::HashMap;
+
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+};
+
+use crate::mail::MessageSet;
+use crate::store::{MailStore, StoreError, Write};
+
+/// The property holding a snoozed message's wake time, in RFC 3339.
+pub const SNOOZED_UNTIL: &str = "seneca.snoozed-until";
+/// The hour "tomorrow morning" and "next week" wake up at.
+const MORNING_HOUR: u32 = 8;
+/// Roughly how far off "later today" is.
+const LATER_TODAY_HOURS: i64 = 3;
+
+/// A few hours from now, on the hour.
+pub fn later_today(now: &DateTime<Local>) -> DateTime<Local> {
+    let later = *now + Duration::hours(LATER_TODAY_HOURS);
+    at(
+        later.date().naive_local(),
+        NaiveTime::from_hms(later.hour(), 0, 0),
+    )
+}
+
+/// Tomorrow at the start of the working day.
+pub fn tomorrow_morning(now: &DateTime<Local>) -> DateTime<Local> {
+    let tomorrow = now.date().naive_local().succ();
+    at(tomorrow, NaiveTime::from_hms(MORNING_HOUR, 0, 0))
+}
+
+/// Next Monday morning.
+pub fn next_week(now: &DateTime<Local>) -> DateTime<Local> {
+    let today = now.date().naive_local();
+    let monday = today + Duration::days(7 - today.weekday().num_days_from_monday() as i64);
+    at(monday, NaiveTime::from_hms(MORNING_HOUR, 0, 0))
+}
+
+/// Reads a wake time typed in as "2024-03-01 14:30", "2024-03-01" for that morning or
+/// "14:30" for the next time the clock shows it. Times that have already passed are
+/// refused.
+pub fn parse_custom(text: &str, now: &DateTime<Local>) -> Option<DateTime<Local>> {
+    let text = text.trim();
+    let morning = NaiveTime::from_hms(MORNING_HOUR, 0, 0);
+    let wake = if let Ok(date_time) = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M") {
+        at(date_time.date(), date_time.time())
+    } else if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        at(date, morning)
+    } else if let Ok(time) = NaiveTime::parse_from_str(text, "%H:%M") {
+        let today = at(now.date().naive_local(), time);
+        if today > *now {
+            today
+        } else {
+            at(now.date().naive_local().succ(), time)
+        }
+    } else {
+        return None;
+    };
+    Some(wake).filter(|wake| wake > now)
+}
+
+/// `time` on `date` in the local zone, or the first moment after it should the clocks
+/// skip over it.
+fn at(date: NaiveDate, time: NaiveTime) -> DateTime<Local> {
+    let local = date.and_time(time);
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .unwrap_or_else(|| Local.from_utc_datetime(&local))
+}
+
+/// Reads a `seneca.snoozed-until` value.
+pub fn parse_wake(value: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|wake| wake.with_timezone(&Local))
+}
+
+/// Takes thread `id` out of the inbox until `until`.
+pub fn snooze(id: &str, until: &DateTime<Local>) -> Vec<Write> {
+    let query = format!("thread:{}", id);
+    vec![
+        Write::SetProperty {
+            query: query.clone(),
+            key: SNOOZED_UNTIL.to_string(),
+            value: Some(until.to_rfc3339()),
+        },
+        Write::Tag {
+            query,
+            add: vec!["snoozed".to_string()],
+            remove: vec!["inbox".to_string()],
+        },
+    ]
+}
+
+/// Puts thread `id` back in the inbox straight away.
+pub fn unsnooze(id: &str) -> Vec<Write> {
+    let query = format!("thread:{} and tag:snoozed", id);
+    vec![
+        // Before the tag comes off, while the query still finds the messages
+        Write::SetProperty {
+            query: query.clone(),
+            key: SNOOZED_UNTIL.to_string(),
+            value: None,
+        },
+        Write::Tag {
+            query,
+            add: vec!["inbox".to_string()],
+            remove: vec!["snoozed".to_string()],
+        },
+    ]
+}
+
+/// The changes that wake every thread due by `now`, putting it back in the inbox with
+/// its newest snoozed message unread, and when the next thread is due. Messages
+/// snoozed by other means than Seneca, without a wake time, are left alone.
+pub fn wake_due(
+    store: &mut dyn MailStore,
+    now: &DateTime<Local>,
+) -> Result<(Vec<Write>, Option<DateTime<Local>>), StoreError> {
+    let mut next: Option<DateTime<Local>> = None;
+    // The newest message of each thread to wake, and its date
+    let mut due: HashMap<String, (i64, String)> = HashMap::new();
+    for message in store.messages("tag:snoozed")? {
+        let wake = message
+            .properties
+            .iter()
+            .find(|(key, _)| key == SNOOZED_UNTIL)
+            .and_then(|(_, value)| parse_wake(value));
+        match wake {
+            Some(wake) if wake > *now => {
+                next = Some(next.map_or(wake, |next| next.min(wake)));
+            }
+            Some(_) => {
+                let newest = due
+                    .entry(message.thread_id)
+                    .or_insert((message.date, message.id.clone()));
+                if message.date > newest.0 {
+                    *newest = (message.date, message.id);
+                }
+            }
+            None => {}
+        }
+    }
+
+    let mut writes = Vec::new();
+    for (thread, (_, newest)) in due {
+        writes.extend(unsnooze(&thread));
+        writes.push(Write::Tag {
+            query: MessageSet {
+                thread,
+                messages: vec![newest],
+            }
+            .query(),
+            add: vec!["unread".to_string()],
+            remove: Vec::new(),
+        });
+    }
+    Ok((writes, next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::testing;
+
+    /// A Wednesday morning.
+    fn now() -> DateTime<Local> {
+        Local.ymd(2021, 6, 16).and_hms(10, 25, 0)
+    }
+
+    fn apply(store: &mut dyn MailStore, writes: &[Write]) {
+        for result in store.apply(writes).unwrap() {
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn later_today_is_on_the_hour() {
+        assert_eq!(
+            later_today(&now()),
+            Local.ymd(2021, 6, 16).and_hms(13, 0, 0)
+        );
+    }
+
+    #[test]
+    fn tomorrow_morning_is_the_start_of_the_next_day() {
+        assert_eq!(
+            tomorrow_morning(&now()),
+            Local.ymd(2021, 6, 17).and_hms(MORNING_HOUR, 0, 0)
+        );
+    }
+
+    #[test]
+    fn next_week_is_the_coming_monday() {
+        let monday = Local.ymd(2021, 6, 21).and_hms(MORNING_HOUR, 0, 0);
+        assert_eq!(next_week(&now()), monday);
+        let sunday = Local.ymd(2021, 6, 20).and_hms(22, 0, 0);
+        assert_eq!(next_week(&sunday), monday);
+        let week_after = Local.ymd(2021, 6, 28).and_hms(MORNING_HOUR, 0, 0);
+        assert_eq!(next_week(&monday), week_after);
+    }
+
+    #[test]
+    fn custom_times_are_read_and_past_ones_refused() {
+        assert_eq!(
+            parse_custom("2021-06-20 14:30", &now()),
+            Some(Local.ymd(2021, 6, 20).and_hms(14, 30, 0))
+        );
+        assert_eq!(
+            parse_custom(" 2021-06-20 ", &now()),
+            Some(Local.ymd(2021, 6, 20).and_hms(MORNING_HOUR, 0, 0))
+        );
+        assert_eq!(
+            parse_custom("11:00", &now()),
+            Some(Local.ymd(2021, 6, 16).and_hms(11, 0, 0))
+        );
+        assert_eq!(
+            parse_custom("09:00", &now()),
+            Some(Local.ymd(2021, 6, 17).and_hms(9, 0, 0))
+        );
+        assert_eq!(parse_custom("2021-06-16 09:00", &now()), None);
+        assert_eq!(parse_custom("2021-06-01", &now()), None);
+        assert_eq!(parse_custom("soon", &now()), None);
+    }
+
+    #[test]
+    fn due_threads_wake_with_their_newest_message_unread() {
+        let mut store = MemoryStore::from_dir(&testing::fixtures());
+        let threads = store.query_threads("tag:inbox").unwrap();
+        let (lunch, welcome) = (&threads[0].id, &threads[1].id);
+        let mut writes = snooze(lunch, &(now() - Duration::hours(1)));
+        writes.extend(snooze(welcome, &tomorrow_morning(&now())));
+        writes.push(Write::Tag {
+            query: format!("thread:{}", lunch),
+            add: Vec::new(),
+            remove: vec!["unread".to_string()],
+        });
+        apply(&mut store, &writes);
+        assert_eq!(store.count_messages("tag:inbox").unwrap(), 0);
+
+        let (writes, next) = wake_due(&mut store, &now()).unwrap();
+        assert_eq!(next, Some(tomorrow_morning(&now())));
+        apply(&mut store, &writes);
+        let inbox = store.query_threads("tag:inbox").unwrap();
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(&inbox[0].id, lunch);
+        let unread: Vec<String> = store
+            .messages(&format!("thread:{} and tag:unread", lunch))
+            .unwrap()
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(unread, ["lunch-reply-2@example.com"]);
+        assert_eq!(store.count_messages("tag:snoozed").unwrap(), 1);
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use config::{Config, File, FileFormat};
+use dirs::{data_local_dir, state_dir};
+use druid::widget::Controller;
+use druid::{Env, Event, EventCtx, Point, Size, Widget};
+
+use crate::MailData;
+
+const DEFAULT_WINDOW_SIZE: Size = Size::new(1000.0, 500.0);
+/// Anything smaller is treated as a mistake and the default size used instead.
+const MIN_WINDOW_SIZE: Size = Size::new(300.0, 200.0);
+pub const DEFAULT_SIDEBAR_SPLIT: f64 = 0.15;
+pub const DEFAULT_LIST_SPLIT: f64 = 0.3;
+pub const DEFAULT_BOTTOM_SPLIT: f64 = 0.4;
+
+/// Where the user left things, saved when the main window closes and restored on the next
+/// launch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionState {
+    pub window_size: Size,
+    pub window_position: Option<Point>,
+    /// How much of the window the search sidebar takes up
+    pub sidebar_split: f64,
+    /// How much of the rest the thread list takes up, next to the reading pane
+    pub list_split: f64,
+    /// How much of the rest the thread list takes up, above the reading pane
+    pub bottom_split: f64,
+    /// The query of the search that was selected
+    pub search: Option<String>,
+    /// The id of the thread that was open
+    pub thread: Option<String>,
+    /// The theme chosen in the sidebar, which takes the place of `theme` in config.toml
+    pub theme: Option<String>,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        Self {
+            window_size: DEFAULT_WINDOW_SIZE,
+            window_position: None,
+            sidebar_split: DEFAULT_SIDEBAR_SPLIT,
+            list_split: DEFAULT_LIST_SPLIT,
+            bottom_split: DEFAULT_BOTTOM_SPLIT,
+            search: None,
+            thread: None,
+            theme: None,
+        }
+    }
+}
+
+/// `$XDG_STATE_HOME/seneca/state.toml`, or the local data folder on platforms without a
+/// state folder.
+fn state_file() -> Option<PathBuf> {
+    state_dir()
+        .or_else(data_local_dir)
+        .map(|dir| dir.join("seneca/state.toml"))
+}
+
+/// Quotes `value` as a TOML basic string.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Split ratios outside this range would leave a pane too small to grab the bar again.
+fn valid_split(split: f64) -> bool {
+    (0.02..=0.98).contains(&split)
+}
+
+impl SessionState {
+    /// Reads the state file, falling back to the defaults for anything missing or
+    /// unreadable.
+    pub fn load() -> Self {
+        let defaults = Self::default();
+        let path = match state_file() {
+            Some(path) if path.is_file() => path,
+            _ => return defaults,
+        };
+        let config = match Config::builder()
+            .add_source(File::from(path.as_path()).format(FileFormat::Toml))
+            .build()
+        {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Unable to read {}: {}", path.display(), e);
+                return defaults;
+            }
+        };
+        let float = |key: &str| config.get_float(key).ok();
+        let window_size = match (float("window.width"), float("window.height")) {
+            (Some(width), Some(height))
+                if width >= MIN_WINDOW_SIZE.width && height >= MIN_WINDOW_SIZE.height =>
+            {
+                Size::new(width, height)
+            }
+            _ => defaults.window_size,
+        };
+        let window_position = match (float("window.x"), float("window.y")) {
+            (Some(x), Some(y)) => Some(Point::new(x, y)),
+            _ => None,
+        };
+        Self {
+            window_size,
+            window_position,
+            sidebar_split: float("layout.sidebar-split")
+                .filter(|split| valid_split(*split))
+                .unwrap_or(defaults.sidebar_split),
+            list_split: float("layout.list-split")
+                .filter(|split| valid_split(*split))
+                .unwrap_or(defaults.list_split),
+            bottom_split: float("layout.bottom-split")
+                .filter(|split| valid_split(*split))
+                .unwrap_or(defaults.bottom_split),
+            search: config.get_string("session.search").ok(),
+            thread: config.get_string("session.thread").ok(),
+            theme: config.get_string("session.theme").ok(),
+        }
+    }
+
+    /// The state of the main window and the app as they are now.
+    pub fn capture(data: &MailData, window_size: Size, window_position: Point) -> Self {
+        Self {
+            window_size,
+            window_position: Some(window_position),
+            sidebar_split: data.sidebar_split,
+            list_split: data.list_split,
+            bottom_split: data.bottom_split,
+            search: Some(data.searches.selected.to_string()),
+            thread: data.tabs.current().map(|thread| thread.id.to_string()),
+            theme: Some(data.theme.to_string()),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = state_file()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state folder"))?;
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let mut contents = format!(
+            "[window]\nwidth = {:.1}\nheight = {:.1}\n",
+            self.window_size.width, self.window_size.height
+        );
+        if let Some(position) = self.window_position {
+            contents.push_str(&format!("x = {:.1}\ny = {:.1}\n", position.x, position.y));
+        }
+        contents.push_str(&format!(
+            "\n[layout]\nsidebar-split = {:.4}\nlist-split = {:.4}\nbottom-split = {:.4}\n",
+            self.sidebar_split, self.list_split, self.bottom_split
+        ));
+        contents.push_str("\n[session]\n");
+        if let Some(search) = &self.search {
+            contents.push_str(&format!("search = {}\n", quote(search)));
+        }
+        if let Some(thread) = &self.thread {
+            contents.push_str(&format!("thread = {}\n", quote(thread)));
+        }
+        if let Some(theme) = &self.theme {
+            contents.push_str(&format!("theme = {}\n", quote(theme)));
+        }
+        // Written aside and renamed so that a crash can't leave half a file behind
+        let temporary = path.with_extension("toml.tmp");
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+/// Saves the session state when the main window is closed, while its size and position
+/// can still be read.
+pub struct SaveSession;
+
+impl<W: Widget<MailData>> Controller<MailData, W> for SaveSession {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut MailData,
+        env: &Env,
+    ) {
+        if let Event::WindowCloseRequested = event {
+            let window = ctx.window();
+            let state = SessionState::capture(data, window.get_size(), window.get_position());
+            if let Err(e) = state.save() {
+                eprintln!("Unable to save session state: {}", e);
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+}
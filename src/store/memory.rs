@@ -0,0 +1,541 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{Local, TimeZone};
+use druid::im::Vector;
+use mailparse::{dateparse, parse_mail, MailHeaderMap};
+
+use super::{Files, MailStore, MessageInfo, MessageSource, StoreError, Write};
+use crate::address::{self, Address};
+use crate::mail::{self, Authors, MessageSummary, Thread};
+use crate::snooze;
+
+/// Tags given to fixture messages that don't carry an `X-Seneca-Tags` header.
+const DEFAULT_TAGS: &[&str] = &["inbox", "unread"];
+
+struct Message {
+    id: String,
+    thread_id: String,
+    path: PathBuf,
+    date: i64,
+    tags: BTreeSet<String>,
+    properties: Vec<(String, String)>,
+    from: String,
+    to: String,
+    cc: String,
+    subject: String,
+    body: String,
+    /// Message-IDs from In-Reply-To and References
+    parents: Vec<String>,
+    /// The revision at which the message was last changed
+    lastmod: u64,
+}
+
+impl Message {
+    fn parse(path: &Path, tags: Option<&[String]>) -> Result<Self, String> {
+        let raw = fs::read(path).map_err(|e| e.to_string())?;
+        let parsed = parse_mail(&raw).map_err(|e| e.to_string())?;
+        let headers = &parsed.headers;
+        let header = |name: &str| headers.get_first_value(name).unwrap_or_default();
+
+        let tags = match tags {
+            Some(tags) => tags.iter().cloned().collect(),
+            None => match headers.get_first_value("X-Seneca-Tags") {
+                Some(tags) => tags.split_whitespace().map(String::from).collect(),
+                None => DEFAULT_TAGS.iter().map(|tag| tag.to_string()).collect(),
+            },
+        };
+        let parents = headers
+            .get_all_values("In-Reply-To")
+            .iter()
+            .chain(headers.get_all_values("References").iter())
+            .flat_map(|value| message_ids(value))
+            .collect();
+        let body = if parsed.subparts.is_empty() {
+            parsed.get_body().unwrap_or_default()
+        } else {
+            parsed
+                .subparts
+                .iter()
+                .filter(|part| part.ctype.mimetype.contains("plain"))
+                .filter_map(|part| part.get_body().ok())
+                .collect()
+        };
+
+        Ok(Self {
+            id: message_ids(&header("Message-ID"))
+                .pop()
+                .unwrap_or_else(|| path.display().to_string()),
+            thread_id: String::new(),
+            path: path.to_path_buf(),
+            date: dateparse(&header("Date")).unwrap_or(0),
+            tags,
+            properties: Vec::new(),
+            from: header("From"),
+            to: header("To"),
+            cc: header("Cc"),
+            subject: header("Subject"),
+            body,
+            parents,
+            lastmod: 0,
+        })
+    }
+}
+
+/// The ids in a Message-ID, In-Reply-To or References header, without angle brackets.
+fn message_ids(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == '<' || c == '>' || c.is_whitespace())
+        .filter(|id| id.contains('@'))
+        .map(String::from)
+        .collect()
+}
+
+/// A single search term; a query is a disjunction of conjunctions of these.
+enum Term {
+    All,
+    Tag(String),
+    Thread(String),
+    Id(String),
+    From(String),
+    To(String),
+    Subject(String),
+    Lastmod(u64, Option<u64>),
+    Text(String),
+}
+
+impl Term {
+    fn parse(term: &str) -> Self {
+        let (prefix, value) = match term.find(':') {
+            Some(i) => (&term[..i], term[i + 1..].trim_matches('"')),
+            None => ("", term.trim_matches('"')),
+        };
+        let value = value.to_string();
+        match prefix {
+            _ if term == "*" => Term::All,
+            "tag" | "is" => Term::Tag(value),
+            "thread" => Term::Thread(value),
+            "id" | "mid" => Term::Id(value),
+            "from" => Term::From(value.to_lowercase()),
+            "to" => Term::To(value.to_lowercase()),
+            "subject" => Term::Subject(value.to_lowercase()),
+            "lastmod" => {
+                let mut range = value.splitn(2, "..");
+                let start = range.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+                let end = range.next().and_then(|s| s.parse().ok());
+                Term::Lastmod(start, end)
+            }
+            _ => Term::Text(term.trim_matches('"').to_lowercase()),
+        }
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        let contains = |haystack: &str, needle: &str| haystack.to_lowercase().contains(needle);
+        match self {
+            Term::All => true,
+            Term::Tag(tag) => message.tags.contains(tag),
+            Term::Thread(id) => &message.thread_id == id,
+            Term::Id(id) => &message.id == id,
+            Term::From(from) => contains(&message.from, from),
+            Term::To(to) => contains(&message.to, to) || contains(&message.cc, to),
+            Term::Subject(subject) => contains(&message.subject, subject),
+            Term::Lastmod(start, end) => {
+                message.lastmod >= *start && end.map_or(true, |end| message.lastmod <= end)
+            }
+            Term::Text(text) => {
+                contains(&message.subject, text)
+                    || contains(&message.from, text)
+                    || contains(&message.body, text)
+            }
+        }
+    }
+}
+
+/// A subset of the notmuch query language: `*`, free text, the `tag:`, `thread:`, `id:`,
+/// `from:`, `to:`, `subject:` and `lastmod:` prefixes, negation with `not` or `-`, and
+/// `and`/`or` without parentheses.
+struct Query(Vec<Vec<(bool, Term)>>);
+
+impl Query {
+    fn parse(query: &str) -> Self {
+        let mut alternatives = vec![Vec::new()];
+        let mut negate = false;
+        for word in split_words(query) {
+            match word.as_str() {
+                "or" | "OR" => alternatives.push(Vec::new()),
+                "and" | "AND" => {}
+                "not" | "NOT" => negate = !negate,
+                _ => {
+                    let (negated, term) = match word.strip_prefix('-') {
+                        Some(term) if !term.is_empty() => (!negate, term),
+                        _ => (negate, word.as_str()),
+                    };
+                    alternatives
+                        .last_mut()
+                        .unwrap()
+                        .push((negated, Term::parse(term)));
+                    negate = false;
+                }
+            }
+        }
+        Query(alternatives)
+    }
+
+    /// Whether the query mentions `tag`, which stops it being excluded.
+    fn names_tag(&self, tag: &str) -> bool {
+        self.0
+            .iter()
+            .flatten()
+            .any(|(_, term)| matches!(term, Term::Tag(t) if t == tag))
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        self.0.iter().any(|terms| {
+            !terms.is_empty()
+                && terms
+                    .iter()
+                    .all(|(negated, term)| term.matches(message) != *negated)
+        })
+    }
+}
+
+/// Splits a query on whitespace, keeping double-quoted phrases together.
+fn split_words(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// A mail store held entirely in memory, seeded from a directory of `.eml` files or
+/// maildirs. Tags come from each message's `X-Seneca-Tags` header, and changes are never
+/// written back to the files, which makes it suitable for tests and for trying out the
+/// UI without a notmuch database.
+#[derive(Default)]
+pub struct MemoryStore {
+    messages: Vec<Message>,
+    revision: u64,
+    next_thread: u64,
+    exclude_tags: Vec<String>,
+    show_excluded: bool,
+}
+
+impl MemoryStore {
+    /// Loads every message under `dir`. Files that can't be parsed are skipped.
+    pub fn from_dir(dir: &Path) -> Self {
+        let mut store = Self::default();
+        let mut paths = Vec::new();
+        collect_mail_files(dir, &mut paths);
+        paths.sort();
+        for path in paths {
+            match Message::parse(&path, None) {
+                Ok(message) => store.messages.push(message),
+                Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+            }
+        }
+        store.thread_messages();
+        store
+    }
+
+    /// Leaves messages with these tags out of searches, like notmuch's
+    /// `search.exclude_tags`.
+    pub fn with_exclude_tags(mut self, tags: Vec<String>) -> Self {
+        self.exclude_tags = tags;
+        self
+    }
+
+    /// Groups messages into threads using their In-Reply-To and References headers.
+    fn thread_messages(&mut self) {
+        let index: HashMap<String, usize> = self
+            .messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| (message.id.clone(), i))
+            .collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let mut parent: Vec<usize> = (0..self.messages.len()).collect();
+        for (i, message) in self.messages.iter().enumerate() {
+            for id in &message.parents {
+                if let Some(&j) = index.get(id) {
+                    let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                    parent[a.max(b)] = a.min(b);
+                }
+            }
+        }
+
+        // Keep the ids of existing threads so `thread:` queries stay valid
+        let mut thread_ids: HashMap<usize, String> = HashMap::new();
+        for i in 0..self.messages.len() {
+            let r = root(&mut parent, i);
+            if !self.messages[i].thread_id.is_empty() {
+                thread_ids
+                    .entry(r)
+                    .or_insert_with(|| self.messages[i].thread_id.clone());
+            }
+        }
+        for i in 0..self.messages.len() {
+            let r = root(&mut parent, i);
+            let next_thread = &mut self.next_thread;
+            let id = thread_ids.entry(r).or_insert_with(|| {
+                *next_thread += 1;
+                format!("{:016x}", *next_thread)
+            });
+            self.messages[i].thread_id = id.clone();
+        }
+    }
+
+    /// The messages matching `query`, oldest first, leaving out excluded messages.
+    fn matching(&self, query: &str) -> Vec<&Message> {
+        let query = Query::parse(query);
+        let excluded: Vec<&String> = if self.show_excluded {
+            Vec::new()
+        } else {
+            self.exclude_tags
+                .iter()
+                .filter(|tag| !query.names_tag(tag))
+                .collect()
+        };
+        let mut messages: Vec<&Message> = self
+            .messages
+            .iter()
+            .filter(|message| query.matches(message))
+            .filter(|message| !excluded.iter().any(|tag| message.tags.contains(*tag)))
+            .collect();
+        messages.sort_by_key(|message| message.date);
+        messages
+    }
+
+    /// Every message in a thread, oldest first.
+    fn thread(&self, thread_id: &str) -> Vec<&Message> {
+        let mut messages: Vec<&Message> = self
+            .messages
+            .iter()
+            .filter(|message| message.thread_id == thread_id)
+            .collect();
+        messages.sort_by_key(|message| message.date);
+        messages
+    }
+
+    fn apply_one(&mut self, write: &Write) -> Result<(), String> {
+        let revision = self.revision + 1;
+        match write {
+            Write::Tag { query, add, remove } => {
+                let query = Query::parse(query);
+                for message in self.messages.iter_mut().filter(|m| query.matches(m)) {
+                    let before = message.tags.clone();
+                    message.tags.extend(add.iter().cloned());
+                    for tag in remove {
+                        message.tags.remove(tag);
+                    }
+                    if message.tags != before {
+                        message.lastmod = revision;
+                    }
+                }
+            }
+            Write::SetProperty { query, key, value } => {
+                let query = Query::parse(query);
+                for message in self.messages.iter_mut().filter(|m| query.matches(m)) {
+                    message.properties.retain(|(other, _)| other != key);
+                    if let Some(value) = value {
+                        message.properties.push((key.clone(), value.clone()));
+                    }
+                    message.lastmod = revision;
+                }
+            }
+            Write::Index { path, tags } => {
+                let mut message = Message::parse(path, Some(tags))?;
+                message.lastmod = revision;
+                self.messages.retain(|m| m.path != *path);
+                self.messages.push(message);
+                self.thread_messages();
+            }
+            Write::Remove { path } => {
+                let count = self.messages.len();
+                self.messages.retain(|m| m.path != *path);
+                if self.messages.len() == count {
+                    return Err(format!("{} is not in the store", path.display()));
+                }
+            }
+        }
+        self.revision = revision;
+        Ok(())
+    }
+}
+
+fn collect_mail_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            // Skip the maildir tmp folder, which only holds messages being delivered
+            if path.file_name().map_or(true, |name| name != "tmp") {
+                collect_mail_files(&path, paths);
+            }
+        } else if path.extension().map_or(false, |ext| ext == "eml")
+            || path
+                .parent()
+                .and_then(Path::file_name)
+                .map_or(false, |parent| parent == "cur" || parent == "new")
+        {
+            paths.push(path);
+        }
+    }
+}
+
+impl MailStore for MemoryStore {
+    fn query_threads(&mut self, query: &str) -> Result<Vec<Thread>, StoreError> {
+        // Each thread with a match, and the ids of its messages that matched
+        let mut thread_ids: Vec<(&str, Vec<&str>)> = Vec::new();
+        for message in self.matching(query) {
+            match thread_ids
+                .iter_mut()
+                .find(|(id, _)| *id == message.thread_id)
+            {
+                Some((_, matched)) => matched.push(&message.id),
+                None => thread_ids.push((&message.thread_id, vec![&message.id])),
+            }
+        }
+
+        let mut threads: Vec<(i64, Thread)> = thread_ids
+            .into_iter()
+            .map(|(thread_id, matched)| {
+                let messages = self.thread(thread_id);
+                let newest = messages.last().map_or(0, |message| message.date);
+                let authors: Vec<(Address, bool)> = messages
+                    .iter()
+                    .map(|m| (Authors::author(&m.from), matched.contains(&m.id.as_str())))
+                    .collect();
+                let tags: BTreeSet<&String> =
+                    messages.iter().copied().flat_map(|m| &m.tags).collect();
+                let thread = Thread {
+                    authors: Authors::from_messages(&authors),
+                    date: Arc::new(Local.timestamp(newest, 0)),
+                    subject: messages
+                        .first()
+                        .map(|message| address::decode_words(&message.subject))
+                        .unwrap_or_default(),
+                    summaries: messages
+                        .iter()
+                        .map(|message| MessageSummary {
+                            id: message.id.clone(),
+                            path: Arc::new(message.path.clone()),
+                            date: message.date,
+                            unread: message.tags.iter().any(|tag| tag == "unread"),
+                            flagged: message.tags.iter().any(|tag| tag == "flagged"),
+                            matched: matched.contains(&message.id.as_str()),
+                        })
+                        .collect(),
+                    messages: Vector::new(),
+                    id: thread_id.to_string(),
+                    excluded: tags.iter().any(|tag| self.exclude_tags.contains(tag)),
+                    tags: tags.into_iter().cloned().collect(),
+                    total_messages: messages.len() as u32,
+                    matched_messages: matched.len() as u32,
+                    snippet: messages
+                        .last()
+                        .map(|message| mail::snippet(&message.body))
+                        .unwrap_or_default(),
+                    viewing: false,
+                    loading: false,
+                    snoozed_until: messages
+                        .iter()
+                        .flat_map(|message| &message.properties)
+                        .find(|(key, _)| key == snooze::SNOOZED_UNTIL)
+                        .and_then(|(_, value)| snooze::parse_wake(value))
+                        .map(Arc::new),
+                    choosing_snooze: false,
+                    snooze_time: String::new(),
+                };
+                (newest, thread)
+            })
+            .collect();
+        threads.sort_by_key(|(newest, _)| -newest);
+        Ok(threads.into_iter().map(|(_, thread)| thread).collect())
+    }
+
+    fn messages(&mut self, query: &str) -> Result<Vec<MessageInfo>, StoreError> {
+        Ok(self
+            .matching(query)
+            .into_iter()
+            .map(|message| MessageInfo {
+                id: message.id.clone(),
+                thread_id: message.thread_id.clone(),
+                path: message.path.clone(),
+                date: message.date,
+                tags: message.tags.iter().cloned().collect(),
+                properties: message.properties.clone(),
+                from: message.from.clone(),
+                to: message.to.clone(),
+                cc: message.cc.clone(),
+            })
+            .collect())
+    }
+
+    fn count_messages(&mut self, query: &str) -> Result<u32, StoreError> {
+        Ok(self.matching(query).len() as u32)
+    }
+
+    fn count_unread(&mut self, query: &str) -> Result<u32, StoreError> {
+        Ok(self
+            .matching(query)
+            .iter()
+            .filter(|message| message.tags.contains("unread") && !message.tags.contains("muted"))
+            .count() as u32)
+    }
+
+    fn apply(&mut self, writes: &[Write]) -> Result<Vec<Result<(), String>>, StoreError> {
+        Ok(writes
+            .iter()
+            .map(|write| {
+                self.apply_one(write)
+                    .map_err(|e| format!("Unable to {}: {}", write.describe(), e))
+            })
+            .collect())
+    }
+
+    fn revision(&mut self) -> Result<u64, StoreError> {
+        Ok(self.revision)
+    }
+
+    fn refresh(&mut self) {}
+
+    fn set_show_excluded(&mut self, show: bool) {
+        self.show_excluded = show;
+    }
+
+    fn source(&self) -> Arc<dyn MessageSource> {
+        // The store is seeded from files, which are left where they are
+        Arc::new(Files)
+    }
+}
@@ -0,0 +1,373 @@
+//! The mail database behind Seneca. Everything outside this module talks to a
+//! `MailStore`, so the UI can run against notmuch or against an in-memory store seeded
+//! from `.eml` files.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::mail::Thread;
+
+mod memory;
+mod notmuch_store;
+
+pub use memory::MemoryStore;
+pub use notmuch_store::NotmuchStore;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// The store is locked by another process; the operation can be retried later
+    Busy(String),
+    Failed(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Busy(message) => write!(f, "database is busy: {}", message),
+            StoreError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// A change to the store. Changes are queued and applied in batches whenever the store
+/// can be written to.
+#[derive(Clone, Debug)]
+pub enum Write {
+    /// Add and remove tags on every message matching `query`
+    Tag {
+        query: String,
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Replace the values of property `key` on every message matching `query` with
+    /// `value`, or remove the property if there is none
+    SetProperty {
+        query: String,
+        key: String,
+        value: Option<String>,
+    },
+    /// Index a new file and tag the resulting message
+    Index { path: PathBuf, tags: Vec<String> },
+    /// Forget a file that has been deleted
+    Remove { path: PathBuf },
+}
+
+impl Write {
+    pub fn describe(&self) -> String {
+        match self {
+            Write::Tag { query, .. } => format!("retag {}", query),
+            Write::SetProperty { query, key, .. } => format!("set {} on {}", key, query),
+            Write::Index { path, .. } => format!("index {}", path.display()),
+            Write::Remove { path } => format!("remove {}", path.display()),
+        }
+    }
+}
+
+/// What the store knows about a single message without parsing its file.
+#[derive(Clone, Debug)]
+pub struct MessageInfo {
+    pub id: String,
+    pub thread_id: String,
+    pub path: PathBuf,
+    pub date: i64,
+    pub tags: Vec<String>,
+    /// Seneca's own properties, whose keys start with `seneca.`
+    pub properties: Vec<(String, String)>,
+    pub from: String,
+    pub to: String,
+    pub cc: String,
+}
+
+pub trait MailStore {
+    /// The threads matching `query`, newest first, with their messages left unparsed.
+    fn query_threads(&mut self, query: &str) -> Result<Vec<Thread>, StoreError>;
+
+    /// The messages matching `query`, oldest first.
+    fn messages(&mut self, query: &str) -> Result<Vec<MessageInfo>, StoreError>;
+
+    fn count_messages(&mut self, query: &str) -> Result<u32, StoreError>;
+
+    /// How many unread messages match `query`, leaving out those in muted threads.
+    fn count_unread(&mut self, query: &str) -> Result<u32, StoreError>;
+
+    /// Applies a batch of changes. `Err` means nothing could be applied (for instance
+    /// because the store is locked); otherwise there is one result per change.
+    fn apply(&mut self, writes: &[Write]) -> Result<Vec<Result<(), String>>, StoreError>;
+
+    /// A counter that increases whenever the store changes. Messages changed since a
+    /// revision can be found with a `lastmod:<revision>..` query.
+    fn revision(&mut self) -> Result<u64, StoreError>;
+
+    /// Makes changes made by other processes visible to later queries.
+    fn refresh(&mut self);
+
+    /// Whether searches include messages with an excluded tag. Excluded messages are
+    /// always included when the query names their tag.
+    fn set_show_excluded(&mut self, show: bool);
+
+    /// Where the messages behind the store's paths are read from.
+    fn source(&self) -> Arc<dyn MessageSource>;
+}
+
+/// Reads the messages a store's paths point at. Threads are parsed away from the
+/// database thread, so this is handed out separately from the store, which stays there.
+pub trait MessageSource: Send + Sync {
+    /// The raw message at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// When the message at `path` last changed, if that can be told.
+    fn modified(&self, path: &Path) -> Option<SystemTime>;
+}
+
+/// Messages kept one to a file, as in a maildir.
+pub struct Files;
+
+impl MessageSource for Files {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::{Arc, Mutex};
+
+    use druid::im::Vector;
+
+    use super::*;
+    use crate::cache::MessageCache;
+    use crate::mail::{self, MessageSet};
+    use crate::testing::{self, TempDir};
+
+    /// The messages in `tests/fixtures`, loaded into each kind of store with the same
+    /// tags. The files are copied to a directory of the test's own first, since notmuch
+    /// only indexes files inside its database; it lasts as long as the returned guard.
+    fn stores(name: &str) -> (TempDir, Vec<Box<dyn MailStore>>) {
+        let dir = TempDir::new(name);
+        for entry in fs::read_dir(testing::fixtures()).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+
+        let mut memory = MemoryStore::from_dir(&dir);
+        notmuch::Database::create(&*dir).unwrap();
+        let mut indexed = NotmuchStore::new(dir.as_os_str().to_owned(), Vec::new());
+        let index: Vec<Write> = memory
+            .messages("*")
+            .unwrap()
+            .into_iter()
+            .map(|message| Write::Index {
+                path: message.path,
+                tags: message.tags,
+            })
+            .collect();
+        for result in indexed.apply(&index).unwrap() {
+            result.unwrap();
+        }
+        (dir, vec![Box::new(memory), Box::new(indexed)])
+    }
+
+    fn untag(store: &mut dyn MailStore, query: String, tag: &str) {
+        let write = Write::Tag {
+            query,
+            add: Vec::new(),
+            remove: vec![tag.to_string()],
+        };
+        for result in store.apply(&[write]).unwrap() {
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn search_finds_threads_newest_first() {
+        let (_dir, stores) = stores("search");
+        for mut store in stores {
+            let threads = store.query_threads("tag:inbox").unwrap();
+            let ids: Vec<Vec<&str>> = threads
+                .iter()
+                .map(|thread| {
+                    thread
+                        .summaries
+                        .iter()
+                        .map(|summary| summary.id.as_str())
+                        .collect()
+                })
+                .collect();
+            assert_eq!(
+                ids,
+                [
+                    vec![
+                        "lunch@example.com",
+                        "lunch-reply@example.com",
+                        "lunch-reply-2@example.com"
+                    ],
+                    vec!["welcome@seneca.example"],
+                ]
+            );
+            assert_eq!(threads[0].total_messages, 3);
+            assert!(threads[0].has_unread());
+        }
+    }
+
+    #[test]
+    fn messages_come_oldest_first() {
+        let (_dir, stores) = stores("order");
+        for mut store in stores {
+            let dates: Vec<i64> = store
+                .messages("*")
+                .unwrap()
+                .iter()
+                .map(|message| message.date)
+                .collect();
+            let mut sorted = dates.clone();
+            sorted.sort_unstable();
+            assert_eq!(dates.len(), 5);
+            assert_eq!(dates, sorted);
+        }
+    }
+
+    #[test]
+    fn opening_a_thread_parses_its_messages() {
+        let (_dir, stores) = stores("open");
+        for mut store in stores {
+            let thread = store.query_threads("tag:inbox").unwrap().remove(0);
+            let opened = mail::load_thread(
+                Arc::new(thread),
+                &*store.source(),
+                &Mutex::new(MessageCache::default()),
+                &AtomicU64::new(0),
+                0,
+            )
+            .unwrap();
+            let bodies: Vec<&str> = opened
+                .messages
+                .iter()
+                .map(|email| email.body.trim())
+                .collect();
+            assert_eq!(
+                bodies,
+                [
+                    "Are you free for lunch on Friday?",
+                    "Friday works. The usual place?",
+                    "The usual place, at noon."
+                ]
+            );
+            assert!(!opened.messages[1].unread);
+            assert!(opened.messages[2].unread);
+        }
+    }
+
+    #[test]
+    fn messages_that_cannot_be_read_still_show() {
+        let (dir, stores) = stores("unreadable");
+        for mut store in stores {
+            let mut thread = store.query_threads("tag:inbox").unwrap().remove(0);
+            let missing = Arc::new(dir.join("missing"));
+            thread.summaries[1].path = missing.clone();
+            let opened = mail::load_thread(
+                Arc::new(thread),
+                &*store.source(),
+                &Mutex::new(MessageCache::default()),
+                &AtomicU64::new(0),
+                0,
+            )
+            .unwrap();
+            assert_eq!(opened.messages.len(), 3);
+            assert_eq!(opened.messages[1].path, missing);
+            assert!(opened.messages[1].subject.starts_with("Could not parse"));
+            assert_eq!(opened.messages[2].body.trim(), "The usual place, at noon.");
+        }
+    }
+
+    #[test]
+    fn snippets_come_from_the_newest_message() {
+        let (_dir, stores) = stores("snippets");
+        for mut store in stores {
+            let threads: Vector<Arc<Thread>> = store
+                .query_threads("tag:inbox")
+                .unwrap()
+                .into_iter()
+                .map(|mut thread| {
+                    thread.snippet.clear();
+                    Arc::new(thread)
+                })
+                .collect();
+            let mut found = HashMap::new();
+            mail::load_snippets(
+                &threads,
+                &*store.source(),
+                &Mutex::new(HashMap::new()),
+                &AtomicU64::new(0),
+                0,
+                |batch| found.extend(batch),
+            );
+            assert_eq!(found.len(), threads.len());
+            assert_eq!(found[&threads[0].id], "The usual place, at noon.");
+        }
+    }
+
+    #[test]
+    fn marking_read_takes_off_unread() {
+        let (_dir, stores) = stores("mark-read");
+        for mut store in stores {
+            let set = MessageSet {
+                thread: String::new(),
+                messages: vec!["lunch-reply-2@example.com".to_string()],
+            };
+            untag(&mut *store, set.query(), "unread");
+            let threads = store.query_threads("tag:inbox").unwrap();
+            assert!(!threads[0].has_unread());
+            // The welcome message is unread by default
+            assert_eq!(store.count_messages("tag:unread").unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn unread_counts_leave_out_muted_threads() {
+        let (_dir, stores) = stores("unread");
+        for mut store in stores {
+            assert_eq!(store.count_unread("tag:inbox").unwrap(), 2);
+            let lunch = store.query_threads("tag:inbox").unwrap().remove(0);
+            let write = Write::Tag {
+                query: format!("thread:{}", lunch.id),
+                add: vec!["muted".to_string()],
+                remove: Vec::new(),
+            };
+            for result in store.apply(&[write]).unwrap() {
+                result.unwrap();
+            }
+            assert_eq!(store.count_unread("tag:inbox").unwrap(), 1);
+        }
+    }
+
+    // Seneca has no archive action yet, so this only covers the change it would make
+    #[test]
+    fn archiving_takes_a_thread_out_of_the_inbox() {
+        let (_dir, stores) = stores("archive");
+        for mut store in stores {
+            let lunch = store.query_threads("tag:inbox").unwrap().remove(0);
+            let set = MessageSet {
+                thread: lunch.id.clone(),
+                messages: Vec::new(),
+            };
+            untag(&mut *store, set.query(), "inbox");
+            let inbox = store.query_threads("tag:inbox").unwrap();
+            assert_eq!(inbox.len(), 1);
+            assert_ne!(inbox[0].id, lunch.id);
+            let thread = format!("thread:{}", lunch.id);
+            assert_eq!(store.count_messages(&thread).unwrap(), 3);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{Local, TimeZone};
+use druid::im::Vector;
+use notmuch::{Database, DatabaseMode, Query, Sort};
+
+use super::{Files, MailStore, MessageInfo, MessageSource, StoreError, Write};
+use crate::address::Address;
+use crate::mail::{Authors, MessageSummary, Thread};
+use crate::snooze;
+
+/// The notmuch database. Queries go through a long-lived read-only handle; the write
+/// lock is only taken for the length of a batch of changes so that `notmuch new` and
+/// other clients can get in between.
+pub struct NotmuchStore {
+    location: OsString,
+    /// Messages with these tags are left out of searches unless the query names the tag
+    exclude_tags: Vec<String>,
+    show_excluded: bool,
+    reader: Option<Database>,
+}
+
+fn failed(e: notmuch::Error) -> StoreError {
+    StoreError::Failed(e.to_string())
+}
+
+/// libnotmuch reports another process holding the write lock as a Xapian exception.
+/// Anything else, like a missing database or one we can't write to, won't go away by
+/// waiting.
+fn open_failed(e: notmuch::Error) -> StoreError {
+    match e {
+        notmuch::Error::NotmuchError(notmuch::Status::XapianException) => {
+            StoreError::Busy(e.to_string())
+        }
+        e => failed(e),
+    }
+}
+
+impl NotmuchStore {
+    pub fn new(location: OsString, exclude_tags: Vec<String>) -> Self {
+        Self {
+            location,
+            exclude_tags,
+            show_excluded: false,
+            reader: None,
+        }
+    }
+
+    /// The read-only database, opened on first use.
+    fn reader(&mut self) -> Result<&Database, StoreError> {
+        if self.reader.is_none() {
+            self.reader = Some(
+                Database::open(Path::new(&self.location), DatabaseMode::ReadOnly)
+                    .map_err(failed)?,
+            );
+        }
+        Ok(self.reader.as_ref().unwrap())
+    }
+
+    /// A search query with the configured tags excluded.
+    fn query(&mut self, query: &str) -> Result<Query<'_>, StoreError> {
+        self.reader()?;
+        let db = self.reader.as_ref().unwrap();
+        let query = db.create_query(query).map_err(failed)?;
+        if !self.show_excluded {
+            for tag in &self.exclude_tags {
+                query.add_tag_exclude(tag).map_err(failed)?;
+            }
+        }
+        Ok(query)
+    }
+
+    fn apply_one(db: &Database, write: &Write) -> Result<(), notmuch::Error> {
+        match write {
+            Write::Tag { query, add, remove } => {
+                let query = db.create_query(query)?;
+                for message in query.search_messages()? {
+                    for tag in add {
+                        message.add_tag(tag)?;
+                    }
+                    for tag in remove {
+                        message.remove_tag(tag)?;
+                    }
+                }
+                Ok(())
+            }
+            Write::SetProperty { query, key, value } => {
+                let query = db.create_query(query)?;
+                for message in query.search_messages()? {
+                    message.remove_all_properties(Some(key))?;
+                    if let Some(value) = value {
+                        message.add_property(key, value)?;
+                    }
+                }
+                Ok(())
+            }
+            Write::Index { path, tags } => {
+                let message = db.index_file(path, None)?;
+                for tag in tags {
+                    message.add_tag(tag)?;
+                }
+                Ok(())
+            }
+            Write::Remove { path } => db.remove_message(path),
+        }
+    }
+}
+
+impl MailStore for NotmuchStore {
+    fn query_threads(&mut self, query: &str) -> Result<Vec<Thread>, StoreError> {
+        let exclude_tags = self.exclude_tags.clone();
+        let query = self.query(query)?;
+        // notmuch only gives the authors as one string, which can't be split reliably
+        // since names can have commas in them
+        let matched: HashSet<String> = query
+            .search_messages()
+            .map_err(failed)?
+            .map(|message| message.id().to_string())
+            .collect();
+        let threads = query.search_threads().map_err(failed)?;
+        Ok(threads
+            .map(|thread| {
+                // Threads keep their excluded messages, they just can't match on them
+                let tags: Vector<String> = thread.tags().collect();
+                let excluded = tags.iter().any(|tag| exclude_tags.contains(tag));
+                let mut snoozed_until = None;
+                let mut authors = Vec::new();
+                let summaries = thread
+                    .messages()
+                    .map(|message| {
+                        let path = message.filename();
+                        let date = message.date();
+                        let tags: Vec<String> = message.tags().collect();
+                        let id = message.id().to_string();
+                        let from = match message.header("from") {
+                            Ok(Some(from)) => Authors::author(&from),
+                            _ => Address::new(None, ""),
+                        };
+                        let is_match = matched.contains(&id);
+                        authors.push((from, is_match));
+                        if tags.iter().any(|tag| tag == "snoozed") {
+                            snoozed_until = message
+                                .properties(snooze::SNOOZED_UNTIL, true)
+                                .find_map(|(_, value)| snooze::parse_wake(&value))
+                                .map(Arc::new)
+                                .or(snoozed_until);
+                        }
+                        MessageSummary {
+                            id,
+                            path: Arc::new(path),
+                            date,
+                            unread: tags.iter().any(|tag| tag == "unread"),
+                            flagged: tags.iter().any(|tag| tag == "flagged"),
+                            matched: is_match,
+                        }
+                    })
+                    .collect::<Vector<MessageSummary>>();
+                Thread {
+                    authors: Authors::from_messages(&authors),
+                    date: Arc::new(Local.timestamp(thread.newest_date(), 0)),
+                    subject: thread.subject().clone().into(),
+                    summaries,
+                    messages: Vector::new(),
+                    id: thread.id().into(),
+                    tags,
+                    total_messages: thread.total_messages() as u32,
+                    matched_messages: thread.matched_messages() as u32,
+                    // Read later, away from the database thread
+                    snippet: String::new(),
+                    excluded,
+                    viewing: false,
+                    loading: false,
+                    snoozed_until,
+                    choosing_snooze: false,
+                    snooze_time: String::new(),
+                }
+            })
+            .collect())
+    }
+
+    fn messages(&mut self, query: &str) -> Result<Vec<MessageInfo>, StoreError> {
+        let query = self.query(query)?;
+        // libnotmuch sorts newest first unless told otherwise
+        query.set_sort(Sort::OldestFirst);
+        let messages = query.search_messages().map_err(failed)?;
+        let header = |message: &notmuch::Message, name: &str| -> String {
+            match message.header(name) {
+                Ok(Some(value)) => value.to_string(),
+                _ => String::new(),
+            }
+        };
+        Ok(messages
+            .map(|message| MessageInfo {
+                id: message.id().to_string(),
+                thread_id: message.thread_id().to_string(),
+                path: message.filename(),
+                date: message.date(),
+                tags: message.tags().collect(),
+                properties: message.properties("seneca.", false).collect(),
+                from: header(&message, "from"),
+                to: header(&message, "to"),
+                cc: header(&message, "cc"),
+            })
+            .collect())
+    }
+
+    fn count_messages(&mut self, query: &str) -> Result<u32, StoreError> {
+        let query = self.query(query)?;
+        query.count_messages().map_err(failed)
+    }
+
+    fn count_unread(&mut self, query: &str) -> Result<u32, StoreError> {
+        self.count_messages(&format!("({}) and tag:unread and not tag:muted", query))
+    }
+
+    fn apply(&mut self, writes: &[Write]) -> Result<Vec<Result<(), String>>, StoreError> {
+        let db = Database::open(Path::new(&self.location), DatabaseMode::ReadWrite)
+            .map_err(open_failed)?;
+        let results = writes
+            .iter()
+            .map(|write| {
+                Self::apply_one(&db, write)
+                    .map_err(|e| format!("Unable to {}: {}", write.describe(), e))
+            })
+            .collect();
+        // Closing the database releases the write lock
+        drop(db);
+        // The reader only sees the database as it was when it was opened
+        self.reader = None;
+        Ok(results)
+    }
+
+    fn revision(&mut self) -> Result<u64, StoreError> {
+        Ok(self.reader()?.revision().revision)
+    }
+
+    fn refresh(&mut self) {
+        self.reader = None;
+    }
+
+    fn set_show_excluded(&mut self, show: bool) {
+        self.show_excluded = show;
+    }
+
+    fn source(&self) -> Arc<dyn MessageSource> {
+        Arc::new(Files)
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use config::Config;
+use druid::Color;
+
+use crate::theme::parse_hex;
+
+/// Tags that aren't shown as pills unless `hidden-tags` says otherwise, mostly because
+/// the thread list shows them some other way.
+const DEFAULT_HIDDEN_TAGS: &[&str] = &[
+    "unread",
+    "attachment",
+    "flagged",
+    "muted",
+    "replied",
+    "signed",
+    "snoozed",
+    "encrypted",
+];
+
+/// How tags are shown in the thread list, from the `[tag-colors]` table and the
+/// `hidden-tags` list in `config.toml`.
+#[derive(Clone, Debug)]
+pub struct TagStyles {
+    colors: HashMap<String, Color>,
+    hidden: Vec<String>,
+}
+
+impl Default for TagStyles {
+    fn default() -> Self {
+        Self {
+            colors: HashMap::new(),
+            hidden: DEFAULT_HIDDEN_TAGS
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl TagStyles {
+    pub fn from_config(config: &Config) -> Self {
+        let mut styles = Self::default();
+        if let Ok(colors) = config.get_table("tag-colors") {
+            for (tag, value) in colors {
+                match value.into_str().ok().and_then(|hex| parse_hex(&hex)) {
+                    Some(color) => {
+                        styles.colors.insert(tag, color);
+                    }
+                    None => eprintln!("Ignoring colour for tag {}", tag),
+                }
+            }
+        }
+        if let Ok(hidden) = config.get_array("hidden-tags") {
+            styles.hidden = hidden
+                .into_iter()
+                .filter_map(|value| value.into_str().ok())
+                .collect();
+        }
+        styles
+    }
+
+    /// The pill colour of `tag`, if it has one of its own.
+    pub fn color(&self, tag: &str) -> Option<&Color> {
+        self.colors.get(tag)
+    }
+
+    pub fn is_hidden(&self, tag: &str) -> bool {
+        self.hidden.iter().any(|hidden| hidden == tag)
+    }
+}
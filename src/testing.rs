@@ -0,0 +1,53 @@
+//! Helpers shared by the unit tests.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::mail::Thread;
+use crate::store::{MailStore, MemoryStore};
+
+/// A directory of a test's own in the system's temporary directory, removed along with
+/// everything in it when the guard is dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// An empty directory for the test `name`, which must differ between tests since
+    /// they run at the same time.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("seneca-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The sample messages in `tests/fixtures`.
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Every thread in the fixtures, newest first.
+pub fn fixture_threads() -> Vec<Arc<Thread>> {
+    MemoryStore::from_dir(&fixtures())
+        .query_threads("*")
+        .unwrap()
+        .into_iter()
+        .map(Arc::new)
+        .collect()
+}
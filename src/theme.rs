@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+
+use config::{Config, File, FileFormat, Value};
+use druid::im::Vector;
+use druid::widget::Controller;
+use druid::{
+    theme, ArcStr, Color, Env, Event, EventCtx, FontDescriptor, FontFamily, FontWeight, HotKey,
+    Key, SysMods, UpdateCtx, Widget, WidgetExt,
+};
+
+use crate::{
+    MailData, BODY_FONT, BORDER_COLOR, ERROR_COLOR, FLAGGED_COLOR, METADATA_FONT, MONOSPACE_FONT,
+    SEARCH_BACKGROUND_COLOR, SEARCH_SELECTED_COLOR, SEARCH_TEXT_COLOR, SECONDARY_TEXT_COLOR,
+    SUBJECT_FONT, TAG_COLOR, TAG_TEXT_COLOR, THREAD_BACKGROUND_COLOR, THREAD_HEIGHT,
+    THREAD_SELECTED_COLOR, UI_FONT, UI_FONT_LARGE, UI_FONT_LIGHT, UNREAD_COLOR,
+};
+
+const MIN_ZOOM: f64 = 0.5;
+const MAX_ZOOM: f64 = 3.0;
+const ZOOM_STEP: f64 = 0.1;
+
+/// Every colour a theme sets, by the name used in theme files.
+const COLORS: &[(&str, Key<Color>)] = &[
+    ("window-background", theme::WINDOW_BACKGROUND_COLOR),
+    ("text", theme::TEXT_COLOR),
+    ("secondary-text", SECONDARY_TEXT_COLOR),
+    ("placeholder", theme::PLACEHOLDER_COLOR),
+    ("input-background", theme::BACKGROUND_LIGHT),
+    ("input-border", theme::BORDER_DARK),
+    ("button-light", theme::BUTTON_LIGHT),
+    ("button-dark", theme::BUTTON_DARK),
+    ("selection", theme::SELECTED_TEXT_BACKGROUND_COLOR),
+    ("cursor", theme::CURSOR_COLOR),
+    ("focus", theme::PRIMARY_LIGHT),
+    ("scrollbar", theme::SCROLLBAR_COLOR),
+    ("thread-background", THREAD_BACKGROUND_COLOR),
+    ("thread-selected", THREAD_SELECTED_COLOR),
+    ("border", BORDER_COLOR),
+    ("search-background", SEARCH_BACKGROUND_COLOR),
+    ("search-selected", SEARCH_SELECTED_COLOR),
+    ("search-text", SEARCH_TEXT_COLOR),
+    ("unread", UNREAD_COLOR),
+    ("flagged", FLAGGED_COLOR),
+    ("tag", TAG_COLOR),
+    ("tag-text", TAG_TEXT_COLOR),
+    ("error", ERROR_COLOR),
+];
+
+/// Every font a theme sets, by the name used in theme files.
+const FONTS: &[(&str, Key<FontDescriptor>)] = &[
+    ("ui", UI_FONT),
+    ("ui-large", UI_FONT_LARGE),
+    ("ui-light", UI_FONT_LIGHT),
+    ("subject", SUBJECT_FONT),
+    ("metadata", METADATA_FONT),
+    ("body", BODY_FONT),
+    ("monospace", MONOSPACE_FONT),
+];
+
+/// The `{ r = .., g = .., b = .. }` colours older versions read from `config.toml`,
+/// and the theme colours they stand for now.
+const LEGACY_COLORS: &[(&str, &str)] = &[
+    ("thread-background-color", "thread-background"),
+    ("thread-selected-color", "thread-selected"),
+    ("border-color", "border"),
+    ("search-background-color", "search-background"),
+    ("search-selected-color", "search-selected"),
+];
+
+const LIGHT: &[(&str, &str)] = &[
+    ("window-background", "#ffffff"),
+    ("text", "#000000"),
+    ("secondary-text", "#808080"),
+    ("placeholder", "#808080"),
+    ("input-background", "#ffffff"),
+    ("input-border", "#a0a0a0"),
+    ("button-light", "#f4f4f4"),
+    ("button-dark", "#dcdcdc"),
+    ("selection", "#a8dadc"),
+    ("cursor", "#000000"),
+    ("focus", "#2980b9"),
+    ("scrollbar", "#909090"),
+    ("thread-background", "#ffffff"),
+    ("thread-selected", "#a8dadc"),
+    ("border", "#2980b9"),
+    ("search-background", "#2c3e50"),
+    ("search-selected", "#a8dadc"),
+    ("search-text", "#ffffff"),
+    ("unread", "#14d9eb"),
+    ("flagged", "#e8a317"),
+    ("tag", "#e4e7eb"),
+    ("tag-text", "#3c4650"),
+    ("error", "#c82828"),
+];
+
+const DARK: &[(&str, &str)] = &[
+    ("window-background", "#1e2127"),
+    ("text", "#dcdfe4"),
+    ("secondary-text", "#8b929c"),
+    ("placeholder", "#6b717a"),
+    ("input-background", "#282c34"),
+    ("input-border", "#4b5263"),
+    ("button-light", "#3a3f4b"),
+    ("button-dark", "#2c313a"),
+    ("selection", "#3e4451"),
+    ("cursor", "#dcdfe4"),
+    ("focus", "#61afef"),
+    ("scrollbar", "#5c6370"),
+    ("thread-background", "#1e2127"),
+    ("thread-selected", "#2f4f5f"),
+    ("border", "#3b82b0"),
+    ("search-background", "#15191f"),
+    ("search-selected", "#3b6978"),
+    ("search-text", "#e6e6e6"),
+    ("unread", "#33c3d6"),
+    ("flagged", "#e5c07b"),
+    ("tag", "#3a3f4b"),
+    ("tag-text", "#c8ccd4"),
+    ("error", "#e06c75"),
+];
+
+/// Parses `#rgb`, `#rrggbb` or `#rrggbbaa`.
+pub fn parse_hex(hex: &str) -> Option<Color> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    let channel = |i: usize, width: usize| {
+        let value = u8::from_str_radix(digits.get(i * width..(i + 1) * width)?, 16).ok()?;
+        // A single hex digit stands for both digits of the channel, as in CSS
+        Some(if width == 1 { value * 17 } else { value })
+    };
+    match digits.len() {
+        3 => Some(Color::rgb8(channel(0, 1)?, channel(1, 1)?, channel(2, 1)?)),
+        6 => Some(Color::rgb8(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?)),
+        8 => Some(Color::rgba8(
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Reads the colour tables of `LEGACY_COLORS` from `config.toml`, by theme colour name.
+fn legacy_colors(config: &Config) -> Vec<(&'static str, Color)> {
+    let mut colors = Vec::new();
+    for (key, name) in LEGACY_COLORS {
+        let table = match config.get_table(key) {
+            Ok(table) => table,
+            Err(_) => continue,
+        };
+        let channel = |channel: &str| {
+            let value = table.get(channel)?.clone().into_int().ok()?;
+            u8::try_from(value).ok()
+        };
+        match (channel("r"), channel("g"), channel("b")) {
+            (Some(r), Some(g), Some(b)) => colors.push((*name, Color::rgb8(r, g, b))),
+            _ => eprintln!(
+                "Ignoring {} in config.toml: r, g and b must be 0 to 255",
+                key
+            ),
+        }
+    }
+    colors
+}
+
+fn parse_family(family: &str) -> FontFamily {
+    match family {
+        "system-ui" => FontFamily::SYSTEM_UI,
+        "serif" => FontFamily::SERIF,
+        "sans-serif" => FontFamily::SANS_SERIF,
+        "monospace" => FontFamily::MONOSPACE,
+        name => FontFamily::new_unchecked(name),
+    }
+}
+
+fn parse_weight(weight: &Value) -> Option<FontWeight> {
+    if let Ok(weight) = weight.clone().into_int() {
+        return Some(FontWeight::new(weight.clamp(1, 1000) as u16));
+    }
+    Some(match weight.clone().into_str().ok()?.as_str() {
+        "thin" => FontWeight::THIN,
+        "light" => FontWeight::LIGHT,
+        "regular" => FontWeight::REGULAR,
+        "medium" => FontWeight::MEDIUM,
+        "semi-bold" => FontWeight::SEMI_BOLD,
+        "bold" => FontWeight::BOLD,
+        "black" => FontWeight::BLACK,
+        _ => return None,
+    })
+}
+
+/// A complete set of colours and fonts.
+#[derive(Clone)]
+pub struct Theme {
+    colors: HashMap<&'static str, Color>,
+    fonts: HashMap<&'static str, FontDescriptor>,
+}
+
+impl Theme {
+    fn builtin(colors: &[(&'static str, &str)]) -> Self {
+        let ui = FontDescriptor::new(FontFamily::SYSTEM_UI).with_size(13.0);
+        Self {
+            colors: colors
+                .iter()
+                .map(|(name, hex)| (*name, parse_hex(hex).expect("Bad built-in colour")))
+                .collect(),
+            fonts: vec![
+                ("ui", ui.clone()),
+                ("ui-large", ui.clone().with_size(22.0)),
+                ("ui-light", ui.clone().with_weight(FontWeight::LIGHT)),
+                ("subject", ui.clone().with_size(18.0)),
+                (
+                    "metadata",
+                    ui.clone().with_size(11.0).with_weight(FontWeight::LIGHT),
+                ),
+                ("body", ui),
+                (
+                    "monospace",
+                    FontDescriptor::new(FontFamily::MONOSPACE).with_size(12.0),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self::builtin(LIGHT)
+    }
+
+    pub fn dark() -> Self {
+        Self::builtin(DARK)
+    }
+
+    /// This theme with the `colors` and `fonts` tables of a theme file laid over it.
+    /// Anything the file leaves out or gets wrong keeps this theme's value.
+    fn extend(&self, name: &str, config: &Config) -> Self {
+        let mut theme = self.clone();
+        if let Ok(colors) = config.get_table("colors") {
+            for (key, value) in colors {
+                let color = value.into_str().ok().and_then(|hex| parse_hex(&hex));
+                match (COLORS.iter().find(|(known, _)| *known == key), color) {
+                    (Some((known, _)), Some(color)) => {
+                        theme.colors.insert(*known, color);
+                    }
+                    _ => eprintln!("Ignoring colour {} in theme {}", key, name),
+                }
+            }
+        }
+        if let Ok(fonts) = config.get_table("fonts") {
+            theme.set_fonts(name, fonts);
+        }
+        theme
+    }
+
+    /// Changes fonts from a table like `ui = { family = "serif", size = 14 }`. Any
+    /// attribute that is left out keeps its current value.
+    fn set_fonts(&mut self, source: &str, fonts: HashMap<String, Value>) {
+        for (key, value) in fonts {
+            let known = match FONTS.iter().find(|(known, _)| *known == key) {
+                Some((known, _)) => *known,
+                None => {
+                    eprintln!("Ignoring font {} in {}", key, source);
+                    continue;
+                }
+            };
+            let table = value.into_table().unwrap_or_default();
+            let mut font = self.fonts[known].clone();
+            if let Some(family) = table.get("family") {
+                if let Ok(family) = family.clone().into_str() {
+                    font.family = parse_family(&family);
+                }
+            }
+            if let Some(size) = table.get("size") {
+                if let Ok(size) = size.clone().into_float() {
+                    font = font.with_size(size);
+                }
+            }
+            if let Some(weight) = table.get("weight").and_then(parse_weight) {
+                font = font.with_weight(weight);
+            }
+            self.fonts.insert(known, font);
+        }
+    }
+
+    /// Sets every themed key in `env`, with font sizes multiplied by `zoom`.
+    pub fn apply(&self, zoom: f64, env: &mut Env) {
+        for (name, key) in COLORS {
+            env.set(key.clone(), self.colors[name].clone());
+        }
+        for (name, key) in FONTS {
+            let font = &self.fonts[name];
+            env.set(key.clone(), font.clone().with_size(font.size * zoom));
+        }
+        // Room for the senders and subject lines plus the gaps around them
+        let lines = self.fonts["ui"].size + self.fonts["ui-light"].size;
+        env.set(THREAD_HEIGHT, (lines * zoom * 2.3).round());
+    }
+}
+
+/// The built-in `light` and `dark` themes, followed by any theme files in the `themes`
+/// folder next to `config.toml`. A theme file is named after its theme and looks like
+///
+/// ```toml
+/// base = "dark"
+///
+/// [colors]
+/// unread = "#ff8800"
+///
+/// [fonts]
+/// ui = { family = "serif", size = 14, weight = "regular" }
+/// ```
+///
+/// Fonts in the `[fonts]` table of `config.toml` apply on top of every theme.
+pub struct Themes {
+    themes: Vec<(ArcStr, Theme)>,
+}
+
+impl Themes {
+    pub fn load(folder: &Path, config: &Config) -> Arc<Self> {
+        let mut themes = vec![
+            (ArcStr::from("light"), Theme::light()),
+            (ArcStr::from("dark"), Theme::dark()),
+        ];
+        let mut paths: Vec<_> = match std::fs::read_dir(folder) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "toml"))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        paths.sort();
+        for path in paths {
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            let config = match Config::builder()
+                .add_source(File::from(path.as_path()).format(FileFormat::Toml))
+                .build()
+            {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Unable to read theme {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let base = config
+                .get_string("base")
+                .unwrap_or_else(|_| "light".to_string());
+            let theme = match themes.iter().find(|(known, _)| **known == *base) {
+                Some((_, base)) => base.extend(&name, &config),
+                None => {
+                    eprintln!("Theme {} is based on unknown theme {}", name, base);
+                    Theme::light().extend(&name, &config)
+                }
+            };
+            themes.retain(|(known, _)| **known != *name);
+            themes.push((ArcStr::from(name), theme));
+        }
+        if let Ok(fonts) = config.get_table("fonts") {
+            for (_, theme) in &mut themes {
+                theme.set_fonts("config.toml", fonts.clone());
+            }
+        }
+        let legacy = legacy_colors(config);
+        if !legacy.is_empty() {
+            eprintln!(
+                "Colours like thread-background-color in config.toml are deprecated and will \
+                 stop working; set them in the [colors] table of a theme file instead."
+            );
+            for (_, theme) in &mut themes {
+                theme.colors.extend(legacy.iter().cloned());
+            }
+        }
+        Arc::new(Self { themes })
+    }
+
+    pub fn names(&self) -> Vector<ArcStr> {
+        self.themes.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Sets every themed key in `env`, using the light theme if `name` is unknown.
+    pub fn apply(&self, name: &str, zoom: f64, env: &mut Env) {
+        self.themes
+            .iter()
+            .find(|(known, _)| **known == *name)
+            .unwrap_or(&self.themes[0])
+            .1
+            .apply(zoom, env);
+    }
+}
+
+/// Handles the zoom shortcuts, and redraws everything when the theme or zoom level
+/// changes since widgets don't otherwise notice.
+struct Appearance;
+
+impl<W: Widget<MailData>> Controller<MailData, W> for Appearance {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut MailData,
+        env: &Env,
+    ) {
+        if let Event::KeyDown(key) = event {
+            let zoom = if HotKey::new(SysMods::Cmd, "=").matches(key)
+                || HotKey::new(SysMods::CmdShift, "+").matches(key)
+            {
+                Some(data.zoom + ZOOM_STEP)
+            } else if HotKey::new(SysMods::Cmd, "-").matches(key) {
+                Some(data.zoom - ZOOM_STEP)
+            } else if HotKey::new(SysMods::Cmd, "0").matches(key) {
+                Some(1.0)
+            } else {
+                None
+            };
+            if let Some(zoom) = zoom {
+                // Round so repeated steps don't accumulate floating point error
+                data.zoom = ((zoom * 10.0).round() / 10.0).clamp(MIN_ZOOM, MAX_ZOOM);
+                ctx.set_handled();
+                return;
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &MailData,
+        data: &MailData,
+        env: &Env,
+    ) {
+        if old_data.theme != data.theme || old_data.zoom != data.zoom {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+        child.update(ctx, old_data, data, env)
+    }
+}
+
+/// Wraps a window's root widget so that it follows `MailData::theme` and
+/// `MailData::zoom`.
+pub fn themed(
+    themes: &Arc<Themes>,
+    widget: impl Widget<MailData> + 'static,
+) -> impl Widget<MailData> {
+    let themes = themes.clone();
+    widget
+        .controller(Appearance)
+        .env_scope(move |env, data: &MailData| themes.apply(&data.theme, data.zoom, env))
+}
@@ -0,0 +1,134 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::identity::Identity;
+
+/// Splits a command line into words the way a POSIX shell would: words are separated
+/// by whitespace, single quotes keep everything up to the next one, double quotes
+/// keep everything but backslash escapes, and a backslash outside quotes escapes the
+/// next character. Nothing is expanded, so `~` and `$HOME` reach the command as is.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    let unclosed = |quote: char| format!("Unclosed {} in transport command: {}", quote, command);
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(unclosed('\'')),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if "\"\\$`".contains(c) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(unclosed('"')),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(unclosed('"')),
+                    }
+                }
+            }
+            '\\' => {
+                let word = word.get_or_insert_with(String::new);
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Hands a complete RFC 5322 message to the identity's sendmail-compatible transport
+/// command, which is expected to read recipients from the headers (as `sendmail -t`
+/// and `msmtp -t` do). The command is split into words with shell quoting rules, but
+/// isn't run by a shell.
+pub fn send(identity: &Identity, message: &[u8]) -> Result<(), String> {
+    let words = split_command(&identity.transport)?;
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| format!("No transport configured for {}", identity.address))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Unable to run {}: {}", program, e))?;
+
+    child
+        .stdin
+        .take()
+        .expect("transport stdin was not captured")
+        .write_all(message)
+        .map_err(|e| format!("Unable to write message to {}: {}", program, e))?;
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("{} did not finish: {}", program, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} failed ({}): {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_split_on_whitespace() {
+        assert_eq!(split_command("  msmtp  -t ").unwrap(), vec!["msmtp", "-t"]);
+    }
+
+    #[test]
+    fn quotes_keep_spaces() {
+        assert_eq!(
+            split_command(r#"msmtp -C '/home/me/My Mail/rc' -a "my \"work\"" -t"#).unwrap(),
+            vec![
+                "msmtp",
+                "-C",
+                "/home/me/My Mail/rc",
+                "-a",
+                "my \"work\"",
+                "-t"
+            ]
+        );
+    }
+
+    #[test]
+    fn backslashes_escape_outside_quotes() {
+        assert_eq!(
+            split_command(r"sendmail -f me\ too -t ''").unwrap(),
+            vec!["sendmail", "-f", "me too", "-t", ""]
+        );
+    }
+
+    #[test]
+    fn unclosed_quotes_are_an_error() {
+        assert!(split_command("msmtp -a 'work").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use druid::piet::{Text, TextLayout, TextLayoutBuilder};
+use druid::widget::TextBox;
+use druid::{
+    theme, BoxConstraints, Env, Event, EventCtx, KbKey, LayoutCtx, LifeCycle, LifeCycleCtx,
+    PaintCtx, Point, Rect, RenderContext, Size, UpdateCtx, Widget, WidgetPod,
+};
+
+use crate::contacts::{Contact, ADDRESS_BOOK};
+use crate::{THREAD_BACKGROUND_COLOR, THREAD_SELECTED_COLOR, UI_FONT};
+
+const MAX_SUGGESTIONS: usize = 6;
+
+/// Suggestions are sized by the UI font so that they follow the zoom level.
+fn suggestion_height(env: &Env) -> f64 {
+    (env.get(UI_FONT).size * 1.6).round()
+}
+
+/// What the text in an `Autocomplete` box holds, which decides which part of it is
+/// completed and how a chosen contact is written back.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CompletionMode {
+    /// A comma separated address list, as in a To or Cc field
+    Addresses,
+    /// A notmuch query, where `from:`, `to:` and `cc:` terms are completed
+    Query,
+}
+
+/// A text box that offers contacts from the address book as the user types. Arrow keys
+/// move through the suggestions, Tab or Enter accepts one and Escape dismisses them.
+pub struct Autocomplete {
+    textbox: WidgetPod<String, TextBox<String>>,
+    mode: CompletionMode,
+    suggestions: Vec<Contact>,
+    selected: usize,
+    on_submit: Option<Box<dyn Fn(&mut EventCtx, &mut String, &Env)>>,
+}
+
+impl Autocomplete {
+    pub fn new(mode: CompletionMode) -> Self {
+        Self {
+            textbox: WidgetPod::new(TextBox::new()),
+            mode,
+            suggestions: Vec::new(),
+            selected: 0,
+            on_submit: None,
+        }
+    }
+
+    pub fn with_placeholder(mut self, placeholder: impl Into<String>) -> Self {
+        self.textbox.widget_mut().set_placeholder(placeholder);
+        self
+    }
+
+    /// Called when Enter is pressed while no suggestion is showing.
+    pub fn on_submit(
+        mut self,
+        on_submit: impl Fn(&mut EventCtx, &mut String, &Env) + 'static,
+    ) -> Self {
+        self.on_submit = Some(Box::new(on_submit));
+        self
+    }
+
+    /// The part of `text` that is currently being completed, if any.
+    fn current_term<'a>(&self, text: &'a str) -> Option<&'a str> {
+        match self.mode {
+            CompletionMode::Addresses => text.rsplit(',').next().map(str::trim_start),
+            CompletionMode::Query => {
+                let word = text.rsplit(char::is_whitespace).next()?;
+                ["from:", "to:", "cc:"]
+                    .iter()
+                    .find(|prefix| word.starts_with(*prefix))
+                    .map(|prefix| &word[prefix.len()..])
+            }
+        }
+    }
+
+    /// Replaces the term being completed with the chosen contact.
+    fn accept(&self, text: &mut String, contact: &Contact) {
+        match self.mode {
+            CompletionMode::Addresses => {
+                let keep = text.rfind(',').map(|i| i + 1).unwrap_or(0);
+                text.truncate(keep);
+                if keep > 0 {
+                    text.push(' ');
+                }
+                text.push_str(&format!("{}, ", contact.address));
+            }
+            CompletionMode::Query => {
+                let keep = text.rfind(':').map(|i| i + 1).unwrap_or(0);
+                text.truncate(keep);
+                text.push_str(&format!("{} ", contact.address.email));
+            }
+        }
+    }
+
+    fn refresh_suggestions(&mut self, text: &str) {
+        self.suggestions = match self.current_term(text) {
+            Some(term) if !term.is_empty() => {
+                ADDRESS_BOOK.read().unwrap().complete(term, MAX_SUGGESTIONS)
+            }
+            _ => Vec::new(),
+        };
+        self.selected = 0;
+    }
+}
+
+impl Widget<String> for Autocomplete {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut String, env: &Env) {
+        match event {
+            Event::KeyDown(key) if !self.suggestions.is_empty() => match key.key {
+                KbKey::ArrowDown => {
+                    self.selected = (self.selected + 1) % self.suggestions.len();
+                    ctx.request_paint();
+                    ctx.set_handled();
+                    return;
+                }
+                KbKey::ArrowUp => {
+                    self.selected =
+                        (self.selected + self.suggestions.len() - 1) % self.suggestions.len();
+                    ctx.request_paint();
+                    ctx.set_handled();
+                    return;
+                }
+                KbKey::Tab | KbKey::Enter => {
+                    let contact = self.suggestions[self.selected].clone();
+                    self.accept(data, &contact);
+                    self.suggestions.clear();
+                    ctx.request_layout();
+                    ctx.set_handled();
+                    return;
+                }
+                KbKey::Escape => {
+                    self.suggestions.clear();
+                    ctx.request_layout();
+                    ctx.set_handled();
+                    return;
+                }
+                _ => {}
+            },
+            Event::KeyDown(key) if key.key == KbKey::Enter => {
+                if let Some(on_submit) = &self.on_submit {
+                    on_submit(ctx, data, env);
+                    ctx.set_handled();
+                    return;
+                }
+            }
+            Event::MouseDown(mouse) => {
+                let textbox_height = self.textbox.layout_rect().height();
+                if mouse.pos.y > textbox_height {
+                    let row = ((mouse.pos.y - textbox_height) / suggestion_height(env)) as usize;
+                    if let Some(contact) = self.suggestions.get(row).cloned() {
+                        self.accept(data, &contact);
+                        self.suggestions.clear();
+                        ctx.request_layout();
+                        ctx.set_handled();
+                        return;
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.textbox.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &String, env: &Env) {
+        if let LifeCycle::FocusChanged(false) = event {
+            self.suggestions.clear();
+            ctx.request_layout();
+        }
+        self.textbox.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &String, data: &String, env: &Env) {
+        if old_data != data {
+            self.refresh_suggestions(data);
+            ctx.request_layout();
+        }
+        self.textbox.update(ctx, data, env);
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &String,
+        env: &Env,
+    ) -> Size {
+        let textbox_size = self.textbox.layout(ctx, bc, data, env);
+        self.textbox.set_layout_rect(
+            ctx,
+            data,
+            env,
+            Rect::from_origin_size(Point::ORIGIN, textbox_size),
+        );
+        let height = textbox_size.height + suggestion_height(env) * self.suggestions.len() as f64;
+        bc.constrain(Size::new(textbox_size.width, height))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &String, env: &Env) {
+        self.textbox.paint(ctx, data, env);
+
+        let width = ctx.size().width;
+        let font = env.get(UI_FONT);
+        let row_height = suggestion_height(env);
+        let mut y = self.textbox.layout_rect().height();
+        for (i, contact) in self.suggestions.iter().enumerate() {
+            let row = Rect::new(0., y, width, y + row_height);
+            let background = if i == self.selected {
+                env.get(THREAD_SELECTED_COLOR)
+            } else {
+                env.get(THREAD_BACKGROUND_COLOR)
+            };
+            ctx.fill(row, &background);
+            let layout = ctx
+                .text()
+                .new_text_layout(contact.address.to_string())
+                .font(font.family.clone(), font.size)
+                .text_color(env.get(theme::TEXT_COLOR))
+                .build()
+                .unwrap();
+            let text_y = y + (row_height - layout.size().height) / 2.;
+            ctx.draw_text(&layout, Point::new(4., text_y));
+            y += row_height;
+        }
+    }
+}
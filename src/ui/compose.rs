@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use druid::im::Vector;
+use druid::widget::{
+    Button, Controller, CrossAxisAlignment, Either, Flex, Label, LineBreaking, Maybe, Radio,
+    Scroll, SizedBox, TextBox,
+};
+use druid::{
+    lens, Env, Event, EventCtx, LifeCycle, LifeCycleCtx, TimerToken, UpdateCtx, Widget, WidgetExt,
+};
+
+use crate::compose::Draft;
+use crate::identity::Identity;
+use crate::ui::autocomplete::{Autocomplete, CompletionMode};
+use crate::{MailData, DISCARD_DRAFT, SAVE_DRAFT, SEND_DRAFT, SEND_LATER};
+
+const FIELD_LABEL_WIDTH: f64 = 60.0;
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// The follow-up reminders on offer, in days without a reply.
+const FOLLOW_UPS: &[(&str, Option<u32>)] = &[
+    ("Off", None),
+    ("No reply in 1 day", Some(1)),
+    ("No reply in 3 days", Some(3)),
+    ("No reply in a week", Some(7)),
+];
+
+/// Periodically asks for the draft to be saved to the drafts maildir, if it has been
+/// edited since the last save.
+struct Autosave {
+    id: u64,
+    timer: TimerToken,
+    dirty: bool,
+}
+
+impl<W: Widget<Draft>> Controller<Draft, W> for Autosave {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut Draft,
+        env: &Env,
+    ) {
+        if let Event::Timer(token) = event {
+            if *token == self.timer {
+                if self.dirty {
+                    ctx.submit_command(SAVE_DRAFT.with(self.id));
+                    self.dirty = false;
+                }
+                self.timer = ctx.request_timer(AUTOSAVE_INTERVAL);
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &Draft,
+        env: &Env,
+    ) {
+        if let LifeCycle::WidgetAdded = event {
+            self.timer = ctx.request_timer(AUTOSAVE_INTERVAL);
+        }
+        child.lifecycle(ctx, event, data, env)
+    }
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &Draft,
+        data: &Draft,
+        env: &Env,
+    ) {
+        if !old_data.same_message(data) {
+            self.dirty = true;
+        }
+        child.update(ctx, old_data, data, env)
+    }
+}
+
+fn field(label: &str, input: impl Widget<Draft> + 'static) -> impl Widget<Draft> {
+    Flex::row()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Label::new(label)
+                .with_font(crate::UI_FONT)
+                .fix_width(FIELD_LABEL_WIDTH),
+        )
+        .with_flex_child(input, 1.0)
+        .padding((6., 3.))
+}
+
+/// Builds the root widget for a compose window. Each window looks up its own draft in
+/// `MailData::drafts` by the id it was opened with.
+pub fn compose_window(id: u64, identities: &Vector<Identity>) -> impl Widget<MailData> {
+    compose(id, identities).lens(lens::Map::new(
+        move |data: &MailData| data.drafts.get(&id).cloned(),
+        move |data: &mut MailData, draft: Option<Draft>| {
+            if let (Some(draft), true) = (draft, data.drafts.contains_key(&id)) {
+                data.drafts.insert(id, draft);
+            }
+        },
+    ))
+}
+
+fn compose(id: u64, identities: &Vector<Identity>) -> impl Widget<Option<Draft>> {
+    let identities = identities.clone();
+    Maybe::or_empty(move || draft_view(id, &identities))
+}
+
+/// A drop-down list of the identities to send from: a button showing the chosen one,
+/// which opens the list below it.
+fn identity_picker(identities: &Vector<Identity>) -> Box<dyn Widget<Draft>> {
+    if identities.is_empty() {
+        return Box::new(Label::new("No identities configured in config.toml"));
+    }
+    let labels: Vec<String> = identities
+        .iter()
+        .map(|identity| identity.as_address().to_string())
+        .collect();
+
+    let mut choices = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+    for (i, label) in labels.iter().enumerate() {
+        choices.add_child(Button::new(label.as_str()).on_click(
+            move |_ctx, draft: &mut Draft, _env| {
+                draft.identity = i;
+                draft.choosing_identity = false;
+            },
+        ));
+    }
+
+    let picker = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Button::dynamic(move |draft: &Draft, _env: &Env| {
+                let chosen = labels.get(draft.identity).cloned().unwrap_or_default();
+                format!("{} ▾", chosen)
+            })
+            .on_click(|_ctx, draft: &mut Draft, _env| {
+                draft.choosing_identity = !draft.choosing_identity
+            }),
+        )
+        .with_child(Either::new(
+            |draft: &Draft, _env| draft.choosing_identity,
+            choices,
+            SizedBox::empty(),
+        ));
+    Box::new(picker)
+}
+
+fn draft_view(id: u64, identities: &Vector<Identity>) -> impl Widget<Draft> {
+    let from = identity_picker(identities);
+    let mut follow_up = Flex::row();
+    for (label, days) in FOLLOW_UPS {
+        follow_up.add_child(Radio::new(*label, *days).lens(Draft::follow_up));
+    }
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Fill)
+        .with_child(field("From", from))
+        .with_child(field(
+            "To",
+            Autocomplete::new(CompletionMode::Addresses).lens(Draft::to),
+        ))
+        .with_child(field(
+            "Cc",
+            Autocomplete::new(CompletionMode::Addresses).lens(Draft::cc),
+        ))
+        .with_child(field(
+            "Bcc",
+            Autocomplete::new(CompletionMode::Addresses).lens(Draft::bcc),
+        ))
+        .with_child(field(
+            "Subject",
+            TextBox::new().expand_width().lens(Draft::subject),
+        ))
+        .with_flex_child(
+            Scroll::new(
+                TextBox::multiline()
+                    .with_font(crate::UI_FONT)
+                    .expand_width()
+                    .lens(Draft::body),
+            )
+            .vertical()
+            .padding((6., 3.)),
+            1.0,
+        )
+        .with_child(field("Remind", follow_up))
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Send").on_click(move |ctx, _draft: &mut Draft, _env| {
+                        ctx.submit_command(SEND_DRAFT.with(id))
+                    }),
+                )
+                .with_spacer(6.)
+                .with_child(
+                    TextBox::new()
+                        .with_placeholder("2024-03-01 14:30")
+                        .lens(Draft::send_at),
+                )
+                .with_spacer(6.)
+                .with_child(Button::new("Send later").on_click(
+                    move |ctx, _draft: &mut Draft, _env| ctx.submit_command(SEND_LATER.with(id)),
+                ))
+                .with_spacer(6.)
+                .with_child(Button::new("Discard").on_click(
+                    move |ctx, _draft: &mut Draft, _env| ctx.submit_command(DISCARD_DRAFT.with(id)),
+                ))
+                .with_spacer(6.)
+                .with_flex_child(
+                    Label::new(|draft: &Draft, _env: &Env| draft.status.clone())
+                        .with_line_break_mode(LineBreaking::WordWrap),
+                    1.0,
+                )
+                .padding(6.),
+        )
+        .controller(Autosave {
+            id,
+            timer: TimerToken::INVALID,
+            dirty: false,
+        })
+}
@@ -0,0 +1,275 @@
+//! Marking messages read as they're read, following the `mark-read` setting.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use druid::widget::Controller;
+use druid::{
+    BoxConstraints, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Rect,
+    Selector, Size, TimerToken, UpdateCtx, Widget, WidgetPod,
+};
+
+use crate::mail::{Email, MessageSet, Thread};
+use crate::ui::tabs::HIDDEN_TAB;
+use crate::{FLAG, MARK_READ, MARK_UNREAD, UNFLAG};
+
+/// How often messages are checked for having come into view.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Sent down to every message with the part of the window the thread is shown in, and
+/// the messages to leave unread whether they're on show or not.
+const CHECK_SEEN: Selector<(Rect, HashSet<String>)> = Selector::new("check-seen");
+
+/// When the messages of an opened thread are marked read.
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum MarkRead {
+    /// All of them, as soon as the thread is opened
+    Immediately,
+    /// All of them, once the thread has been open this many seconds
+    After(u64),
+    /// Each one as it's scrolled into view
+    Visible,
+    /// Only with the Mark read buttons
+    Manual,
+}
+
+impl MarkRead {
+    /// Reads the `mark-read` setting: "immediately", "visible", "manual" or a number of
+    /// seconds.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "immediately" => Some(MarkRead::Immediately),
+            "visible" => Some(MarkRead::Visible),
+            "manual" => Some(MarkRead::Manual),
+            _ => name.parse().ok().map(MarkRead::After),
+        }
+    }
+}
+
+impl Default for MarkRead {
+    fn default() -> Self {
+        MarkRead::Visible
+    }
+}
+
+/// Whether enough of a message at `message` is on show in `area` to count as read: half
+/// of it, or half of the area for messages too long to fit.
+fn is_seen(area: Rect, message: Rect) -> bool {
+    let shown = area.intersect(message).height();
+    shown > 0. && shown >= message.height().min(area.height()) * 0.5
+}
+
+/// Marks a message read when the check finds it on show.
+pub struct SeenCheck;
+
+impl<W: Widget<Email>> Controller<Email, W> for SeenCheck {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut Email,
+        env: &Env,
+    ) {
+        if let Event::Command(cmd) = event {
+            if let Some((area, kept_unread)) = cmd.get(CHECK_SEEN) {
+                let message = Rect::from_origin_size(ctx.window_origin(), ctx.size());
+                if data.unread && !kept_unread.contains(&data.id) && is_seen(*area, message) {
+                    data.unread = false;
+                }
+                return;
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+/// Wraps a thread's messages, marks them read according to the policy, and tells the
+/// store about messages marked read or unread, or flagged, from within.
+pub struct ReadTracker {
+    child: WidgetPod<Arc<Thread>, Box<dyn Widget<Arc<Thread>>>>,
+    policy: MarkRead,
+    timer: TimerToken,
+    /// Messages marked unread by hand, which stay that way until they're marked read
+    /// by hand too
+    kept_unread: HashSet<String>,
+}
+
+impl ReadTracker {
+    pub fn new(child: impl Widget<Arc<Thread>> + 'static, policy: MarkRead) -> Self {
+        Self {
+            child: WidgetPod::new(Box::new(child)),
+            policy,
+            timer: TimerToken::INVALID,
+            kept_unread: HashSet::new(),
+        }
+    }
+
+    /// How long after `thread` has loaded to first go about marking it read, if at all.
+    fn first_timer(&self, thread: &Thread) -> Option<Duration> {
+        match self.policy {
+            MarkRead::Immediately => Some(Duration::from_secs(0)),
+            MarkRead::After(seconds) => Some(Duration::from_secs(seconds)),
+            MarkRead::Visible => Some(CHECK_INTERVAL),
+            MarkRead::Manual => None,
+        }
+        .filter(|_| !thread.loading)
+    }
+
+    /// Sends the store every message that was marked read or unread, or flagged or
+    /// unflagged, between `old` and `new`.
+    fn report(&mut self, ctx: &mut EventCtx, old: &Thread, new: &Thread) {
+        // The messages that were changed from true to false and from false to true
+        let changes = |field: fn(&Email) -> bool| {
+            let mut off = Vec::new();
+            let mut on = Vec::new();
+            for (old, new) in old.messages.iter().zip(new.messages.iter()) {
+                match (field(old), field(new)) {
+                    (true, false) => off.push(new.id.clone()),
+                    (false, true) => on.push(new.id.clone()),
+                    _ => (),
+                }
+            }
+            (off, on)
+        };
+        // Only the messages that were loaded, since the thread may have gained more
+        // in the store since
+        let set = |messages: Vec<String>| MessageSet {
+            thread: new.id.clone(),
+            messages,
+        };
+        let (read, unread) = changes(|mail| mail.unread);
+        for id in &read {
+            self.kept_unread.remove(id);
+        }
+        self.kept_unread.extend(unread.iter().cloned());
+        if !read.is_empty() {
+            ctx.submit_command(MARK_READ.with(set(read)));
+        }
+        if !unread.is_empty() {
+            ctx.submit_command(MARK_UNREAD.with(set(unread)));
+        }
+        let (unflagged, flagged) = changes(|mail| mail.flagged);
+        if !flagged.is_empty() {
+            ctx.submit_command(FLAG.with(set(flagged)));
+        }
+        if !unflagged.is_empty() {
+            ctx.submit_command(UNFLAG.with(set(unflagged)));
+        }
+    }
+}
+
+impl Widget<Arc<Thread>> for ReadTracker {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Arc<Thread>, env: &Env) {
+        let old = data.clone();
+        match event {
+            Event::Timer(token) if *token == self.timer => match self.policy {
+                MarkRead::Immediately | MarkRead::After(_) => {
+                    let unread: Vec<String> = data
+                        .messages
+                        .iter()
+                        .filter(|mail| mail.unread)
+                        .map(|mail| mail.id.clone())
+                        .collect();
+                    if !unread.is_empty() {
+                        ctx.submit_command(MARK_READ.with(MessageSet {
+                            thread: data.id.clone(),
+                            messages: unread,
+                        }));
+                    }
+                }
+                _ => {
+                    // Tabs in the background aren't on show, wherever they were last
+                    let hidden = env.try_get(HIDDEN_TAB).unwrap_or(false);
+                    if !hidden && data.has_unread() {
+                        let area = Rect::from_origin_size(ctx.window_origin(), ctx.size());
+                        let check = CHECK_SEEN.with((area, self.kept_unread.clone()));
+                        self.child.event(ctx, &Event::Command(check), data, env);
+                    }
+                    self.timer = ctx.request_timer(CHECK_INTERVAL);
+                }
+            },
+            _ => self.child.event(ctx, event, data, env),
+        }
+        if !old.same(data) {
+            self.report(ctx, &old, data);
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &Arc<Thread>,
+        env: &Env,
+    ) {
+        if let LifeCycle::WidgetAdded = event {
+            if let Some(delay) = self.first_timer(data) {
+                self.timer = ctx.request_timer(delay);
+            }
+        }
+        self.child.lifecycle(ctx, event, data, env)
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &Arc<Thread>,
+        data: &Arc<Thread>,
+        env: &Env,
+    ) {
+        if old_data.loading && !data.loading {
+            if let Some(delay) = self.first_timer(data) {
+                self.timer = ctx.request_timer(delay);
+            }
+        }
+        self.child.update(ctx, data, env)
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &Arc<Thread>,
+        env: &Env,
+    ) -> Size {
+        let size = self.child.layout(ctx, bc, data, env);
+        self.child.set_layout_rect(ctx, data, env, size.to_rect());
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Arc<Thread>, env: &Env) {
+        self.child.paint(ctx, data, env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_count_as_seen_once_half_is_on_show() {
+        let area = Rect::new(0., 0., 500., 400.);
+        assert!(is_seen(area, Rect::new(0., 100., 500., 300.)));
+        assert!(is_seen(area, Rect::new(0., 300., 500., 500.)));
+        assert!(!is_seen(area, Rect::new(0., 350., 500., 550.)));
+        assert!(!is_seen(area, Rect::new(0., 400., 500., 450.)));
+    }
+
+    #[test]
+    fn long_messages_count_as_seen_once_they_fill_half_the_area() {
+        let area = Rect::new(0., 0., 500., 400.);
+        assert!(is_seen(area, Rect::new(0., 200., 500., 2000.)));
+        assert!(!is_seen(area, Rect::new(0., 250., 500., 2000.)));
+    }
+
+    #[test]
+    fn policies_are_read_from_their_names() {
+        assert!(matches!(
+            MarkRead::from_name("manual"),
+            Some(MarkRead::Manual)
+        ));
+        assert!(matches!(MarkRead::from_name("3"), Some(MarkRead::After(3))));
+        assert!(MarkRead::from_name("sometimes").is_none());
+    }
+}
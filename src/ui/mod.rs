@@ -1,4 +1,13 @@
 //pub mod email;
+pub mod autocomplete;
+pub mod compose;
+pub mod mark_read;
+pub mod reading_pane;
 pub mod search_list;
+pub mod snooze;
+pub mod source_view;
+pub mod split;
+pub mod star;
+pub mod tabs;
 pub mod thread_list;
 pub mod virt_list;
\ No newline at end of file
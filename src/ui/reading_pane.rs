@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use druid::widget::Maybe;
+use druid::{lens, Data, Widget, WidgetExt};
+
+use crate::mail::{self, Thread};
+use crate::ui::mark_read::MarkRead;
+use crate::MailData;
+
+/// Where an opened thread is shown.
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum ReadingPane {
+    /// Beside the thread list
+    Right,
+    /// Under the thread list
+    Bottom,
+    /// In a window of its own, one per thread
+    Window,
+}
+
+impl ReadingPane {
+    /// Reads the `reading-pane` setting: "right", "bottom" or "window".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "right" => Some(ReadingPane::Right),
+            "bottom" => Some(ReadingPane::Bottom),
+            "window" => Some(ReadingPane::Window),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ReadingPane::Right => "Right",
+            ReadingPane::Bottom => "Bottom",
+            ReadingPane::Window => "Window",
+        }
+    }
+
+    /// The layout after this one, for cycling through them.
+    pub fn next(self) -> Self {
+        match self {
+            ReadingPane::Right => ReadingPane::Bottom,
+            ReadingPane::Bottom => ReadingPane::Window,
+            ReadingPane::Window => ReadingPane::Right,
+        }
+    }
+}
+
+impl Default for ReadingPane {
+    fn default() -> Self {
+        ReadingPane::Right
+    }
+}
+
+/// Builds the root widget for a thread opened in its own window. Each window looks up
+/// its thread in `MailData::thread_windows` by id.
+pub fn thread_window(id: String, mark_read: MarkRead) -> impl Widget<MailData> {
+    let put_id = id.clone();
+    Maybe::or_empty(move || mail::mail_layout(mark_read)).lens(lens::Map::new(
+        move |data: &MailData| data.thread_windows.get(&id).cloned(),
+        move |data: &mut MailData, thread: Option<Arc<Thread>>| {
+            if let (Some(thread), true) = (thread, data.thread_windows.contains_key(&put_id)) {
+                data.thread_windows.insert(put_id.clone(), thread);
+            }
+        },
+    ))
+}
@@ -1,54 +1,99 @@
-use druid::widget::{Container, Label, List, Padding, Painter, Widget};
+use druid::widget::{
+    Button, Container, CrossAxisAlignment, Flex, Label, List, Padding, Painter, Widget,
+};
 use druid::{
     lens, ArcStr, Data, Insets, LensExt, LinearGradient, RenderContext, UnitPoint, WidgetExt,
 };
 
-use crate::{MailData, Searches, SEARCH_BACKGROUND_COLOR, SEARCH_CHANGE, SEARCH_SELECTED_COLOR};
+use crate::{
+    MailData, Searches, COMPOSE_NEW, SEARCH_BACKGROUND_COLOR, SEARCH_CHANGE, SEARCH_SELECTED_COLOR,
+    SEARCH_TEXT_COLOR,
+};
+
+/// The selected query, and the name, query and unread count of one of the searches.
+type SearchItem = (ArcStr, (ArcStr, ArcStr, u32));
 
 pub fn search_sidebar() -> impl Widget<MailData> {
     let search_lens = (
         lens!(MailData, searches).then(lens!(Searches, selected)),
         lens!(MailData, searches).then(lens!(Searches, search_list)),
     );
+    let searches = List::new(|| {
+        Container::new(
+            Padding::new(
+                Insets::new(6., 0., 0., 0.),
+                Label::dynamic(|data: &SearchItem, _| {
+                    let (name, _, unread) = &data.1;
+                    if *unread > 0 {
+                        format!("{} ({})", name, unread)
+                    } else {
+                        name.to_string()
+                    }
+                })
+                .with_font(crate::UI_FONT_LARGE)
+                .with_text_color(SEARCH_TEXT_COLOR)
+                .on_click(|ctx, data: &mut SearchItem, _env| {
+                    data.0 = data.1 .1.clone();
+                    ctx.submit_command(SEARCH_CHANGE.with(data.0.clone()));
+                }),
+            )
+            .background(Painter::new(|ctx, data: &SearchItem, env| {
+                let bounds = ctx.size().to_rect();
+                if data.1 .1.same(&data.0) {
+                    ctx.fill(
+                        bounds,
+                        &LinearGradient::new(
+                            UnitPoint::LEFT,
+                            UnitPoint::RIGHT,
+                            (
+                                env.get(SEARCH_SELECTED_COLOR),
+                                env.get(SEARCH_BACKGROUND_COLOR),
+                            ),
+                        ),
+                    );
+                } else {
+                    ctx.fill(bounds, &env.get(SEARCH_BACKGROUND_COLOR));
+                }
+            }))
+            .rounded(5.),
+        )
+        .expand_width()
+        .padding(Insets::new(12., 6., 12., 6.))
+    })
+    .lens(search_lens);
+
     Container::new(
-        List::new(|| {
-            Container::new(
-                Padding::new(
-                    Insets::new(6., 0., 0., 0.),
-                    Label::dynamic(|data: &(ArcStr, (ArcStr, ArcStr)), _| data.1 .0.to_string())
-                        .with_font(crate::UI_FONT_LARGE)
-                        .with_text_color(druid::Color::WHITE)
-                        .on_click(|ctx, data: &mut (ArcStr, (ArcStr, ArcStr)), _env| {
-                            data.0 = data.1 .1.clone();
-                            ctx.submit_command(SEARCH_CHANGE.with(data.0.clone()));
-                        }),
-                )
-                .background(Painter::new(
-                    |ctx, data: &(ArcStr, (ArcStr, ArcStr)), env| {
-                        let bounds = ctx.size().to_rect();
-                        if data.1 .1.same(&data.0) {
-                            ctx.fill(
-                                bounds,
-                                &LinearGradient::new(
-                                    UnitPoint::LEFT,
-                                    UnitPoint::RIGHT,
-                                    (
-                                        env.get(SEARCH_SELECTED_COLOR),
-                                        env.get(SEARCH_BACKGROUND_COLOR),
-                                    ),
-                                ),
-                            );
-                        } else {
-                            ctx.fill(bounds, &env.get(SEARCH_BACKGROUND_COLOR));
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(
+                Button::new("New message")
+                    .on_click(|ctx, _data: &mut MailData, _env| ctx.submit_command(COMPOSE_NEW))
+                    .padding(Insets::new(12., 0., 12., 6.)),
+            )
+            .with_child(searches)
+            .with_child(
+                Button::dynamic(|data: &MailData, _env| format!("Theme: {}", data.theme))
+                    .on_click(|_ctx, data: &mut MailData, _env| {
+                        // Cycle through the available themes
+                        let next = data
+                            .theme_names
+                            .index_of(&data.theme)
+                            .map_or(0, |i| (i + 1) % data.theme_names.len());
+                        if let Some(theme) = data.theme_names.get(next) {
+                            data.theme = theme.clone();
                         }
-                    },
-                ))
-                .rounded(5.),
+                    })
+                    .padding(Insets::new(12., 6., 12., 0.)),
             )
-            .expand_width()
-            .padding(Insets::new(12., 6., 12., 6.))
-        })
-        .lens(search_lens),
+            .with_child(
+                Button::dynamic(|data: &MailData, _env| {
+                    format!("Reading pane: {}", data.reading_pane.label())
+                })
+                .on_click(|_ctx, data: &mut MailData, _env| {
+                    data.reading_pane = data.reading_pane.next();
+                })
+                .padding(Insets::new(12., 6., 12., 0.)),
+            ),
     )
     .padding(Insets::new(12., 12., 0., 12.))
     .background(SEARCH_BACKGROUND_COLOR)
@@ -0,0 +1,109 @@
+//! The snooze button in the reading pane, and the choice of wake times behind it.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use druid::lens::InArc;
+use druid::widget::{Button, CrossAxisAlignment, Either, Flex, Label, SizedBox, TextBox};
+use druid::{Env, EventCtx, Widget, WidgetExt};
+
+use crate::dates::DATE_FORMAT;
+use crate::mail::Thread;
+use crate::snooze;
+use crate::{SNOOZE, UNSNOOZE};
+
+fn snooze_until(ctx: &mut EventCtx, thread: &Thread, until: DateTime<Local>) {
+    ctx.submit_command(SNOOZE.with((thread.id.clone(), until)));
+}
+
+/// A button that snoozes the thread, or wakes it if it's already snoozed.
+pub fn snooze_button() -> impl Widget<Arc<Thread>> {
+    Button::dynamic(|thread: &Arc<Thread>, _env: &Env| {
+        if thread.snoozed_until.is_some() {
+            "Unsnooze".to_string()
+        } else {
+            "Snooze...".to_string()
+        }
+    })
+    .on_click(|ctx, thread: &mut Arc<Thread>, _env| {
+        if thread.snoozed_until.is_some() {
+            ctx.submit_command(UNSNOOZE.with(thread.id.clone()));
+        } else {
+            let thread = Arc::make_mut(thread);
+            thread.choosing_snooze = !thread.choosing_snooze;
+        }
+    })
+}
+
+/// The wake times to choose from, shown once the snooze button has been clicked, or
+/// when the thread wakes if it's snoozed.
+pub fn snooze_choices() -> impl Widget<Arc<Thread>> {
+    let choice = |label: &str, wake: fn(&DateTime<Local>) -> DateTime<Local>| {
+        Button::new(label).on_click(move |ctx, thread: &mut Arc<Thread>, _env| {
+            snooze_until(ctx, thread, wake(&Local::now()));
+        })
+    };
+    let choices = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(choice("Later today", snooze::later_today))
+                .with_spacer(5.)
+                .with_child(choice("Tomorrow morning", snooze::tomorrow_morning))
+                .with_spacer(5.)
+                .with_child(choice("Next week", snooze::next_week)),
+        )
+        .with_spacer(5.)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    TextBox::new()
+                        .with_placeholder("2024-03-01 14:30")
+                        .lens(InArc::new(Thread::snooze_time)),
+                )
+                .with_spacer(5.)
+                .with_child(Button::new("Snooze").on_click(
+                    |ctx, thread: &mut Arc<Thread>, _env| {
+                        if let Some(until) =
+                            snooze::parse_custom(&thread.snooze_time, &Local::now())
+                        {
+                            snooze_until(ctx, thread, until);
+                        }
+                    },
+                ))
+                .with_spacer(5.)
+                .with_child(
+                    Label::new(|thread: &Arc<Thread>, _env: &Env| {
+                        if thread.snooze_time.trim().is_empty() {
+                            return String::new();
+                        }
+                        match snooze::parse_custom(&thread.snooze_time, &Local::now()) {
+                            Some(until) => {
+                                format!("Until {}", DATE_FORMAT.read().unwrap().long(&until))
+                            }
+                            None => "Not a time to come".to_string(),
+                        }
+                    })
+                    .with_font(crate::METADATA_FONT),
+                ),
+        );
+
+    Either::new(
+        |thread: &Arc<Thread>, _env| thread.snoozed_until.is_some(),
+        Label::new(
+            |thread: &Arc<Thread>, _env: &Env| match &thread.snoozed_until {
+                Some(until) => format!(
+                    "Snoozed until {}",
+                    DATE_FORMAT.read().unwrap().long(&**until)
+                ),
+                None => String::new(),
+            },
+        )
+        .with_font(crate::METADATA_FONT),
+        Either::new(
+            |thread: &Arc<Thread>, _env| thread.choosing_snooze,
+            choices,
+            SizedBox::empty(),
+        ),
+    )
+}
@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use druid::widget::{Button, CrossAxisAlignment, Flex, Label, Scroll, TextBox};
+use druid::{lens, Application, Data, Env, Lens, Widget, WidgetExt};
+
+use crate::MailData;
+
+/// The raw RFC 5322 source of a single message, as shown in its own window.
+#[derive(Clone, Data, Lens, Default)]
+pub struct SourceView {
+    pub raw: Arc<String>,
+    pub search: String,
+}
+
+impl SourceView {
+    pub fn new(raw: String) -> Self {
+        Self {
+            raw: Arc::new(raw),
+            search: String::new(),
+        }
+    }
+
+    /// The full source, or only the numbered lines containing the search term.
+    fn displayed(&self) -> String {
+        if self.search.is_empty() {
+            return self.raw.to_string();
+        }
+        let needle = self.search.to_lowercase();
+        self.raw
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.to_lowercase().contains(&needle))
+            .map(|(number, line)| format!("{:>5}: {}", number + 1, line))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn match_count(&self) -> usize {
+        let needle = self.search.to_lowercase();
+        self.raw
+            .lines()
+            .filter(|line| line.to_lowercase().contains(&needle))
+            .count()
+    }
+}
+
+/// Builds the root widget for a "View source" window. Each window looks up its own
+/// entry in `MailData::source_views` by the path of the message it was opened for.
+pub fn source_window(path: Arc<PathBuf>) -> impl Widget<MailData> {
+    let put_path = path.clone();
+    source_view().lens(lens::Map::new(
+        move |data: &MailData| data.source_views.get(&path).cloned().unwrap_or_default(),
+        move |data: &mut MailData, view: SourceView| {
+            if data.source_views.contains_key(&put_path) {
+                data.source_views.insert(put_path.clone(), view);
+            }
+        },
+    ))
+}
+
+fn source_view() -> impl Widget<SourceView> {
+    // The source is shown in a text box so that it can be selected and copied, but any
+    // edits are thrown away.
+    let source_lens = lens::Map::new(
+        |view: &SourceView| view.displayed(),
+        |_: &mut SourceView, _: String| {},
+    );
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Fill)
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    TextBox::new()
+                        .with_placeholder("Search source")
+                        .expand_width()
+                        .lens(SourceView::search),
+                    1.0,
+                )
+                .with_spacer(6.)
+                .with_child(Label::dynamic(|view: &SourceView, _env: &Env| {
+                    if view.search.is_empty() {
+                        format!("{} lines", view.raw.lines().count())
+                    } else {
+                        format!("{} matching lines", view.match_count())
+                    }
+                }))
+                .with_spacer(6.)
+                .with_child(Button::new("Copy all").on_click(
+                    |_ctx, view: &mut SourceView, _env| {
+                        Application::global()
+                            .clipboard()
+                            .put_string(view.raw.as_str());
+                    },
+                ))
+                .padding(6.),
+        )
+        .with_flex_child(
+            Scroll::new(
+                TextBox::multiline()
+                    .with_font(crate::MONOSPACE_FONT)
+                    .expand_width()
+                    .lens(source_lens),
+            )
+            .vertical(),
+            1.0,
+        )
+}
@@ -0,0 +1,169 @@
+//! Two panes side by side (or one above the other) with a bar between them that can be
+//! dragged to resize them. Unlike druid's `Split`, the position of the bar lives in the
+//! app data, so that it can be saved and restored.
+
+use druid::widget::Axis;
+use druid::{
+    BoxConstraints, Cursor, Data, Env, Event, EventCtx, LayoutCtx, Lens, LensExt, LifeCycle,
+    LifeCycleCtx, PaintCtx, Point, Rect, RenderContext, Size, UpdateCtx, Widget, WidgetPod,
+};
+
+use crate::BORDER_COLOR;
+
+/// The thickness of the visible bar.
+const BAR_SIZE: f64 = 1.0;
+/// How far either side of the bar the mouse can be to pick it up.
+const GRAB_DISTANCE: f64 = 3.0;
+/// Neither pane can be dragged smaller than this.
+const MIN_PANE_SIZE: f64 = 60.0;
+
+pub struct Split<T, L> {
+    axis: Axis,
+    /// The fraction of the space given to the first pane
+    ratio: L,
+    first: WidgetPod<T, Box<dyn Widget<T>>>,
+    second: WidgetPod<T, Box<dyn Widget<T>>>,
+    /// Where the bar starts along the split axis, as of the last layout
+    bar_position: f64,
+}
+
+impl<T: Data, L: Lens<T, f64>> Split<T, L> {
+    /// Panes side by side, the first on the left.
+    pub fn columns(
+        ratio: L,
+        first: impl Widget<T> + 'static,
+        second: impl Widget<T> + 'static,
+    ) -> Self {
+        Self::new(Axis::Horizontal, ratio, first, second)
+    }
+
+    /// Panes one above the other, the first on top.
+    pub fn rows(
+        ratio: L,
+        first: impl Widget<T> + 'static,
+        second: impl Widget<T> + 'static,
+    ) -> Self {
+        Self::new(Axis::Vertical, ratio, first, second)
+    }
+
+    fn new(
+        axis: Axis,
+        ratio: L,
+        first: impl Widget<T> + 'static,
+        second: impl Widget<T> + 'static,
+    ) -> Self {
+        Self {
+            axis,
+            ratio,
+            first: WidgetPod::new(Box::new(first)),
+            second: WidgetPod::new(Box::new(second)),
+            bar_position: 0.0,
+        }
+    }
+
+    fn on_bar(&self, pos: Point) -> bool {
+        let along = self.axis.major_pos(pos);
+        let centre = self.bar_position + BAR_SIZE / 2.0;
+        (along - centre).abs() <= GRAB_DISTANCE
+    }
+
+    /// The ratio that puts the bar under `pos`, keeping both panes a usable size.
+    fn ratio_at(&self, pos: Point, size: Size) -> f64 {
+        let available = self.axis.major(size) - BAR_SIZE;
+        if available <= 2.0 * MIN_PANE_SIZE {
+            return 0.5;
+        }
+        let along = self.axis.major_pos(pos) - BAR_SIZE / 2.0;
+        along.clamp(MIN_PANE_SIZE, available - MIN_PANE_SIZE) / available
+    }
+
+    fn cursor(&self) -> Cursor {
+        match self.axis {
+            Axis::Horizontal => Cursor::ResizeLeftRight,
+            Axis::Vertical => Cursor::ResizeUpDown,
+        }
+    }
+}
+
+impl<T: Data, L: Lens<T, f64>> Widget<T> for Split<T, L> {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut T, env: &Env) {
+        match event {
+            Event::MouseDown(mouse) if mouse.button.is_left() && self.on_bar(mouse.pos) => {
+                ctx.set_active(true);
+                ctx.set_handled();
+                return;
+            }
+            Event::MouseMove(mouse) if ctx.is_active() => {
+                let ratio = self.ratio_at(mouse.pos, ctx.size());
+                self.ratio.with_mut(data, |current| *current = ratio);
+                ctx.set_cursor(&self.cursor());
+                ctx.set_handled();
+                return;
+            }
+            Event::MouseUp(_) if ctx.is_active() => {
+                ctx.set_active(false);
+                ctx.set_handled();
+                return;
+            }
+            Event::MouseMove(mouse) => {
+                if self.on_bar(mouse.pos) {
+                    ctx.set_cursor(&self.cursor());
+                } else {
+                    ctx.clear_cursor();
+                }
+            }
+            _ => {}
+        }
+        self.first.event(ctx, event, data, env);
+        self.second.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &T, env: &Env) {
+        self.first.lifecycle(ctx, event, data, env);
+        self.second.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &T, data: &T, env: &Env) {
+        let old_ratio = self.ratio.get(old_data);
+        if !old_ratio.same(&self.ratio.get(data)) {
+            ctx.request_layout();
+        }
+        self.first.update(ctx, data, env);
+        self.second.update(ctx, data, env);
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &T, env: &Env) -> Size {
+        let size = bc.max();
+        let available = (self.axis.major(size) - BAR_SIZE).max(0.0);
+        let minor = self.axis.minor(size);
+        let ratio = self.ratio.get(data).clamp(0.0, 1.0);
+        let first_size = (available * ratio).round();
+        let second_size = available - first_size;
+
+        let first_bc = BoxConstraints::tight(Size::from(self.axis.pack(first_size, minor)));
+        let first = self.first.layout(ctx, &first_bc, data, env);
+        self.first
+            .set_layout_rect(ctx, data, env, Rect::from_origin_size(Point::ORIGIN, first));
+
+        self.bar_position = first_size;
+        let second_origin = Point::from(self.axis.pack(first_size + BAR_SIZE, 0.0));
+        let second_bc = BoxConstraints::tight(Size::from(self.axis.pack(second_size, minor)));
+        let second = self.second.layout(ctx, &second_bc, data, env);
+        self.second.set_layout_rect(
+            ctx,
+            data,
+            env,
+            Rect::from_origin_size(second_origin, second),
+        );
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &T, env: &Env) {
+        self.first.paint(ctx, data, env);
+        self.second.paint(ctx, data, env);
+        let size = ctx.size();
+        let origin = Point::from(self.axis.pack(self.bar_position, 0.0));
+        let bar = Size::from(self.axis.pack(BAR_SIZE, self.axis.minor(size)));
+        ctx.fill(Rect::from_origin_size(origin, bar), &env.get(BORDER_COLOR));
+    }
+}
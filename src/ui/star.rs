@@ -0,0 +1,87 @@
+//! The star that flags threads and messages.
+
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use druid::kurbo::BezPath;
+use druid::widget::prelude::*;
+use druid::{Rect, Vec2};
+
+use crate::{FLAGGED_COLOR, SECONDARY_TEXT_COLOR};
+
+pub const STAR_SIZE: f64 = 14.0;
+
+/// A five-pointed star filling `bounds`.
+fn star_path(bounds: Rect) -> BezPath {
+    let center = bounds.center();
+    let outer = bounds.width().min(bounds.height()) / 2.;
+    let inner = outer * 0.45;
+    let mut path = BezPath::new();
+    for i in 0..10 {
+        let radius = if i % 2 == 0 { outer } else { inner };
+        let point = center + Vec2::from_angle(i as f64 * PI / 5. - FRAC_PI_2) * radius;
+        if i == 0 {
+            path.move_to(point);
+        } else {
+            path.line_to(point);
+        }
+    }
+    path.close_path();
+    path
+}
+
+/// Paints a star in `bounds`, filled in when `flagged` and otherwise just outlined.
+pub fn paint_star(ctx: &mut PaintCtx, bounds: Rect, flagged: bool, env: &Env) {
+    // Keep the outline inside the bounds
+    let path = star_path(bounds.inset(-1.));
+    if flagged {
+        ctx.fill(path, &env.get(FLAGGED_COLOR));
+    } else {
+        ctx.stroke(path, &env.get(SECONDARY_TEXT_COLOR), 1.);
+    }
+}
+
+/// A star that flags or unflags when clicked.
+pub struct Star;
+
+impl Widget<bool> for Star {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut bool, _env: &Env) {
+        match event {
+            Event::MouseDown(_) => {
+                ctx.set_active(true);
+                ctx.set_handled();
+            }
+            Event::MouseUp(_) if ctx.is_active() => {
+                ctx.set_active(false);
+                if ctx.is_hot() {
+                    *data = !*data;
+                }
+                ctx.set_handled();
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &bool, _env: &Env) {
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &bool, data: &bool, _env: &Env) {
+        if old_data != data {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &bool,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain(Size::new(STAR_SIZE, STAR_SIZE))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &bool, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        paint_star(ctx, bounds, *data, env);
+    }
+}
@@ -0,0 +1,385 @@
+//! The reading pane's tabs. Every open thread keeps its own widget, so switching tabs
+//! leaves each one scrolled where it was.
+
+use std::sync::Arc;
+
+use druid::im::Vector;
+use druid::widget::{
+    Button, Controller, Either, Flex, Label, LineBreaking, List, Painter, Scroll, SizedBox,
+};
+use druid::{
+    lens, BoxConstraints, Data, Env, Event, EventCtx, HotKey, Insets, Key, LayoutCtx, Lens,
+    LifeCycle, LifeCycleCtx, PaintCtx, RenderContext, Size, SysMods, UpdateCtx, Widget, WidgetExt,
+    WidgetPod,
+};
+
+use crate::mail::{self, Thread};
+use crate::ui::mark_read::MarkRead;
+use crate::{
+    MailData, CLOSE_TAB, REOPEN_TAB, SELECT_TAB, THREAD_BACKGROUND_COLOR, THREAD_SELECTED_COLOR,
+};
+
+/// How many closed tabs are remembered for reopening.
+const MAX_CLOSED: usize = 20;
+const TAB_WIDTH: f64 = 160.0;
+/// Set for the events tabs in the background get, which they aren't on show for.
+pub const HIDDEN_TAB: Key<bool> = Key::new("org.westwork.seneca.hidden-tab");
+
+#[derive(Clone, Data, Lens, Default)]
+pub struct Tabs {
+    pub open: Vector<Arc<Thread>>,
+    /// The index in `open` of the tab on show
+    pub active: usize,
+    /// Closed tabs, the most recently closed last
+    pub closed: Vector<Arc<Thread>>,
+}
+
+impl Tabs {
+    /// The thread in the tab on show.
+    pub fn current(&self) -> Option<&Arc<Thread>> {
+        self.open.get(self.active)
+    }
+
+    fn position(&self, id: &str) -> Option<usize> {
+        self.open.iter().position(|thread| thread.id == id)
+    }
+
+    pub fn is_open(&self, id: &str) -> bool {
+        self.position(id).is_some()
+    }
+
+    pub fn select(&mut self, id: &str) {
+        if let Some(index) = self.position(id) {
+            self.active = index;
+        }
+    }
+
+    /// Shows `thread` in the current tab, or in a new one if there are none, and returns
+    /// the thread it took the place of.
+    pub fn show(&mut self, thread: Arc<Thread>) -> Option<Arc<Thread>> {
+        if self.open.is_empty() {
+            self.open.push_back(thread);
+            self.active = 0;
+            None
+        } else {
+            Some(self.open.set(self.active, thread))
+        }
+    }
+
+    /// Opens `thread` in a new tab just after the current one and switches to it.
+    pub fn open_new(&mut self, thread: Arc<Thread>) {
+        let index = if self.open.is_empty() {
+            0
+        } else {
+            self.active + 1
+        };
+        self.open.insert(index, thread);
+        self.active = index;
+    }
+
+    /// Closes the tab showing the thread `id`, remembering it so that it can be reopened.
+    pub fn close(&mut self, id: &str) -> Option<Arc<Thread>> {
+        let index = self.position(id)?;
+        let thread = self.open.remove(index);
+        // The tab after the closed one takes its place, unless it was the last
+        if index < self.active || self.active >= self.open.len() {
+            self.active = self.active.saturating_sub(1);
+        }
+        self.closed.push_back(thread.clone());
+        if self.closed.len() > MAX_CLOSED {
+            self.closed.pop_front();
+        }
+        Some(thread)
+    }
+
+    /// Takes the most recently closed tab off the list of closed tabs.
+    pub fn take_closed(&mut self) -> Option<Arc<Thread>> {
+        self.closed.pop_back()
+    }
+
+    /// Puts a freshly loaded thread into every tab still waiting for it, and returns
+    /// whether there were any.
+    pub fn loaded(&mut self, thread: &Arc<Thread>) -> bool {
+        let mut any = false;
+        for open in self.open.iter_mut() {
+            if open.id == thread.id && open.loading {
+                *open = thread.clone();
+                any = true;
+            }
+        }
+        any
+    }
+}
+
+/// The body of every open tab, only the current one of which is shown.
+struct TabBodies {
+    bodies: Vec<(String, WidgetPod<Arc<Thread>, Box<dyn Widget<Arc<Thread>>>>)>,
+    mark_read: MarkRead,
+}
+
+impl TabBodies {
+    fn new(mark_read: MarkRead) -> Self {
+        Self {
+            bodies: Vec::new(),
+            mark_read,
+        }
+    }
+
+    /// Adds a body for every newly opened tab and drops those of closed tabs, returning
+    /// whether anything changed.
+    fn sync(&mut self, tabs: &Tabs) -> bool {
+        let before = self.bodies.len();
+        self.bodies.retain(|(id, _)| tabs.is_open(id));
+        let mut changed = self.bodies.len() != before;
+        for thread in tabs.open.iter() {
+            if !self.bodies.iter().any(|(id, _)| *id == thread.id) {
+                let body: Box<dyn Widget<Arc<Thread>>> =
+                    Box::new(mail::mail_layout(self.mark_read));
+                self.bodies.push((thread.id.clone(), WidgetPod::new(body)));
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    fn body(
+        &mut self,
+        id: &str,
+    ) -> Option<&mut WidgetPod<Arc<Thread>, Box<dyn Widget<Arc<Thread>>>>> {
+        self.bodies
+            .iter_mut()
+            .find(|(body_id, _)| body_id == id)
+            .map(|(_, body)| body)
+    }
+}
+
+impl Widget<Tabs> for TabBodies {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Tabs, env: &Env) {
+        // Input only goes to the tab on show, everything else to every tab
+        let current = data.current().map(|thread| thread.id.clone());
+        let mut hidden_env = None;
+        for (index, thread) in data.open.clone().iter().enumerate() {
+            let hidden = Some(&thread.id) != current.as_ref();
+            if hidden && !event.should_propagate_to_hidden() {
+                continue;
+            }
+            let env: &Env = if hidden {
+                hidden_env.get_or_insert_with(|| env.clone().adding(HIDDEN_TAB, true))
+            } else {
+                env
+            };
+            if let Some(body) = self.body(&thread.id) {
+                let mut changed = thread.clone();
+                body.event(ctx, event, &mut changed, env);
+                if !changed.same(thread) {
+                    data.open.set(index, changed);
+                }
+            }
+        }
+    }
+
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &Tabs, env: &Env) {
+        if let LifeCycle::WidgetAdded = event {
+            self.sync(data);
+        }
+        let current = data.current().map(|thread| thread.id.as_str());
+        for (id, body) in self.bodies.iter_mut() {
+            if !event.should_propagate_to_hidden() && Some(id.as_str()) != current {
+                continue;
+            }
+            if let Some(thread) = data.open.iter().find(|thread| thread.id == *id) {
+                body.lifecycle(ctx, event, thread, env);
+            }
+        }
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Tabs, data: &Tabs, env: &Env) {
+        // Update the bodies that are already there before adding new ones, which get
+        // their data when they're added
+        for (id, body) in self.bodies.iter_mut() {
+            if let Some(thread) = data.open.iter().find(|thread| thread.id == *id) {
+                body.update(ctx, thread, env);
+            }
+        }
+        if self.sync(data) {
+            ctx.children_changed();
+        }
+        if old_data.active != data.active || !old_data.open.same(&data.open) {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &Tabs, env: &Env) -> Size {
+        let size = bc.max();
+        if let Some(thread) = data.current() {
+            if let Some(body) = self.body(&thread.id) {
+                body.layout(ctx, &BoxConstraints::tight(size), thread, env);
+                body.set_layout_rect(ctx, thread, env, size.to_rect());
+            }
+        }
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Tabs, env: &Env) {
+        if let Some(thread) = data.current() {
+            if let Some(body) = self.body(&thread.id) {
+                body.paint(ctx, thread, env);
+            }
+        }
+    }
+}
+
+/// A row of tab headers, each with the thread's subject and a close button.
+fn tab_strip() -> impl Widget<Tabs> {
+    let headers = List::new(|| {
+        Flex::row()
+            .with_child(
+                Label::new(|tab: &(bool, Arc<Thread>), _env: &Env| tab.1.subject.clone())
+                    .with_font(crate::UI_FONT)
+                    .with_line_break_mode(LineBreaking::Clip)
+                    .fix_width(TAB_WIDTH)
+                    .on_click(|ctx, tab: &mut (bool, Arc<Thread>), _env| {
+                        ctx.submit_command(SELECT_TAB.with(tab.1.id.clone()));
+                    }),
+            )
+            .with_child(
+                Button::new("×").on_click(|ctx, tab: &mut (bool, Arc<Thread>), _env| {
+                    ctx.submit_command(CLOSE_TAB.with(tab.1.id.clone()));
+                }),
+            )
+            .padding(Insets::new(6., 2., 2., 2.))
+            .background(Painter::new(|ctx, tab: &(bool, Arc<Thread>), env| {
+                let bounds = ctx.size().to_rect();
+                let color = if tab.0 {
+                    THREAD_SELECTED_COLOR
+                } else {
+                    THREAD_BACKGROUND_COLOR
+                };
+                ctx.fill(bounds, &env.get(color));
+            }))
+    })
+    .horizontal();
+
+    Scroll::new(headers).horizontal().lens(lens::Map::new(
+        |tabs: &Tabs| {
+            tabs.open
+                .iter()
+                .enumerate()
+                .map(|(index, thread)| (index == tabs.active, thread.clone()))
+                .collect::<Vector<_>>()
+        },
+        |_tabs: &mut Tabs, _headers: Vector<(bool, Arc<Thread>)>| {},
+    ))
+}
+
+/// The reading pane: a tab strip over the thread in the current tab, or nothing while
+/// no threads are open.
+pub fn reading_pane(mark_read: MarkRead) -> impl Widget<Tabs> {
+    Either::new(
+        |tabs: &Tabs, _env| tabs.open.is_empty(),
+        SizedBox::empty(),
+        Flex::column()
+            .with_child(tab_strip())
+            .with_flex_child(TabBodies::new(mark_read), 1.0),
+    )
+}
+
+/// Ctrl+W closes the current tab and Ctrl+Shift+T reopens the last one closed.
+pub struct TabShortcuts;
+
+impl<W: Widget<MailData>> Controller<MailData, W> for TabShortcuts {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut MailData,
+        env: &Env,
+    ) {
+        if let Event::KeyDown(key) = event {
+            if HotKey::new(SysMods::Cmd, "w").matches(key) {
+                if let Some(thread) = data.tabs.current() {
+                    ctx.submit_command(CLOSE_TAB.with(thread.id.clone()));
+                }
+                ctx.set_handled();
+                return;
+            }
+            if HotKey::new(SysMods::CmdShift, "T").matches(key) {
+                ctx.submit_command(REOPEN_TAB);
+                ctx.set_handled();
+                return;
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture_threads;
+
+    fn ids(threads: &Vector<Arc<Thread>>) -> Vec<&str> {
+        threads.iter().map(|thread| thread.id.as_str()).collect()
+    }
+
+    #[test]
+    fn show_replaces_the_current_tab() {
+        let threads = fixture_threads();
+        let mut tabs = Tabs::default();
+        assert!(tabs.show(threads[0].clone()).is_none());
+        let replaced = tabs.show(threads[1].clone()).unwrap();
+        assert_eq!(replaced.id, threads[0].id);
+        assert_eq!(ids(&tabs.open), [threads[1].id.as_str()]);
+    }
+
+    #[test]
+    fn new_tabs_open_after_the_current_one() {
+        let threads = fixture_threads();
+        let mut tabs = Tabs::default();
+        tabs.open_new(threads[0].clone());
+        tabs.open_new(threads[1].clone());
+        tabs.select(&threads[0].id);
+        tabs.open_new(threads[2].clone());
+        assert_eq!(
+            ids(&tabs.open),
+            [
+                threads[0].id.as_str(),
+                threads[2].id.as_str(),
+                threads[1].id.as_str()
+            ]
+        );
+        assert_eq!(tabs.current().unwrap().id, threads[2].id);
+    }
+
+    #[test]
+    fn closed_tabs_can_be_reopened() {
+        let threads = fixture_threads();
+        let mut tabs = Tabs::default();
+        for thread in &threads[..3] {
+            tabs.open_new(thread.clone());
+        }
+        tabs.select(&threads[1].id);
+        tabs.close(&threads[1].id);
+        // The tab after the closed one takes its place
+        assert_eq!(tabs.current().unwrap().id, threads[2].id);
+        tabs.close(&threads[2].id);
+        assert_eq!(tabs.current().unwrap().id, threads[0].id);
+
+        assert_eq!(tabs.take_closed().unwrap().id, threads[2].id);
+        assert_eq!(tabs.take_closed().unwrap().id, threads[1].id);
+        assert!(tabs.take_closed().is_none());
+    }
+
+    #[test]
+    fn loaded_threads_fill_the_tabs_waiting_for_them() {
+        let threads = fixture_threads();
+        let mut tabs = Tabs::default();
+        let mut waiting = (*threads[0]).clone();
+        waiting.loading = true;
+        tabs.open_new(Arc::new(waiting));
+        assert!(!tabs.loaded(&threads[1]));
+        assert!(tabs.loaded(&threads[0]));
+        assert!(!tabs.current().unwrap().loading);
+    }
+}
@@ -1,37 +1,328 @@
+use std::ops::Range;
 use std::sync::Arc;
-use std::time::Duration;
 
+use crate::address::Address;
+use crate::dates::{DATE_FORMAT, REFRESH_INTERVAL};
+use crate::identity::Identity;
+use crate::mail::{Authors, MessageSet};
+use crate::tags::TagStyles;
+use crate::ui::autocomplete::{Autocomplete, CompletionMode};
+use crate::ui::star::{paint_star, STAR_SIZE};
+use crate::ui::tabs::Tabs;
 use crate::{ui::virt_list::VirtList, MailData, Thread};
-use crate::{LOAD_THREAD, MARK_READ, THREAD_BACKGROUND_COLOR, THREAD_SELECTED_COLOR};
+use crate::{
+    ERROR_COLOR, FLAG, LOAD_THREAD, METADATA_FONT, OPEN_TAB, SEARCH_CHANGE, SECONDARY_TEXT_COLOR,
+    SHOW_EXCLUDED, TAG_COLOR, TAG_TEXT_COLOR, THREAD_BACKGROUND_COLOR, THREAD_HEIGHT,
+    THREAD_SELECTED_COLOR, UNFLAG, UNREAD_COLOR,
+};
 use chrono::Local;
+use druid::im::Vector;
 use druid::kurbo::Circle;
-use druid::widget::{CrossAxisAlignment, Flex, Label, LineBreaking, WidgetExt};
+use druid::piet::{PietTextLayout, Text, TextAttribute, TextLayout, TextLayoutBuilder};
+use druid::widget::{
+    Checkbox, Controller, CrossAxisAlignment, Flex, Label, LineBreaking, WidgetExt,
+};
 use druid::{
-    lens, Color, Env, Event, LifeCycle, MouseButton, Point, Rect, RenderContext, Size,
-    TextAlignment, TimerToken, Widget,
+    lens, theme, ArcStr, Color, Data, Env, Event, LensExt, LifeCycle, MouseButton, PaintCtx, Point,
+    Rect, RenderContext, Size, TextAlignment, TimerToken, UpdateCtx, Widget,
 };
-use itertools::Itertools;
 
-const THREAD_HEIGHT: f64 = 60.0;
+/// The space between the pieces of a row.
+const GAP: f64 = 6.0;
+const PILL_PADDING: Size = Size::new(5.0, 1.0);
+/// The space between two tag pills
+const PILL_SPACING: f64 = 2.0;
+/// Tag pills stop before they take up more than this share of the row.
+const MAX_PILLS_SHARE: f64 = 0.5;
+
+fn is_flagged(thread: &Thread) -> bool {
+    thread.tags.iter().any(|tag| tag == "flagged")
+}
+
+/// What clicking the star does: unflag the messages that are flagged, or flag the
+/// newest message that matched the search. True when it flags.
+fn star_click(thread: &Thread) -> (bool, MessageSet) {
+    let (flag, messages) = if is_flagged(thread) {
+        let flagged = thread
+            .summaries
+            .iter()
+            .filter(|summary| summary.flagged)
+            .map(|summary| summary.id.clone())
+            .collect();
+        (false, flagged)
+    } else {
+        let newest = thread
+            .summaries
+            .iter()
+            .filter(|summary| summary.matched)
+            .max_by_key(|summary| summary.date)
+            .or_else(|| thread.summaries.last())
+            .map(|summary| summary.id.clone());
+        (true, newest.into_iter().collect())
+    };
+    let set = MessageSet {
+        thread: thread.id.clone(),
+        messages,
+    };
+    (flag, set)
+}
+
+/// "3" for a thread of three messages, or "1/3" when only one of them matched.
+fn message_count(thread: &Thread) -> String {
+    if thread.total_messages <= 1 {
+        String::new()
+    } else if thread.matched_messages < thread.total_messages {
+        format!("{}/{}", thread.matched_messages, thread.total_messages)
+    } else {
+        thread.total_messages.to_string()
+    }
+}
+
+/// Icons for the muted, replied and attachment tags.
+fn indicators(thread: &Thread) -> String {
+    let has = |tag: &str| thread.tags.iter().any(|t| t == tag);
+    let mut icons = Vec::new();
+    if thread.is_muted() {
+        icons.push("🔇");
+    }
+    if has("replied") {
+        icons.push("↩");
+    }
+    if has("attachment") {
+        icons.push("📎");
+    }
+    icons.join(" ")
+}
+
+/// Whether `author` is one of the user's own addresses.
+fn is_me(author: &Address, identities: &Vector<Identity>) -> bool {
+    identities
+        .iter()
+        .any(|identity| author.email.eq_ignore_ascii_case(&identity.address))
+}
+
+/// Each author's name and whether they wrote one of the messages that matched, with
+/// the user shown as "me" just once.
+fn author_names(authors: &Authors, identities: &Vector<Identity>) -> Vec<(String, bool)> {
+    let mut names: Vec<(String, bool)> = Vec::new();
+    let all = authors
+        .matched
+        .iter()
+        .map(|author| (author, true))
+        .chain(authors.others.iter().map(|author| (author, false)));
+    for (author, matched) in all {
+        let name = if is_me(author, identities) {
+            "me".to_string()
+        } else {
+            author.display_name().to_string()
+        };
+        if !names.iter().any(|(known, _)| *known == name) {
+            names.push((name, matched));
+        }
+    }
+    names
+}
+
+/// Joins the names, keeping the first `keep` and the last when there are more than
+/// that: "Alice, Bob … Zed (7)". Also returns the byte ranges of the text to dim, which
+/// are the authors who didn't match and the marker for those left out.
+fn join_names(names: &[(String, bool)], keep: usize) -> (String, Vec<Range<usize>>) {
+    let mut text = String::new();
+    let mut dimmed = Vec::new();
+    let mut push = |text: &mut String, name: &str, dim: bool| {
+        let start = text.len();
+        text.push_str(name);
+        if dim {
+            dimmed.push(start..text.len());
+        }
+    };
+    let elided = keep + 1 < names.len();
+    let shown = if elided { keep } else { names.len() };
+    for (i, (name, matched)) in names.iter().take(shown).enumerate() {
+        if i > 0 {
+            text.push_str(", ");
+        }
+        push(&mut text, name, !matched);
+    }
+    if elided {
+        let (last, matched) = names.last().unwrap();
+        push(&mut text, " … ", true);
+        push(&mut text, last, !matched);
+        push(&mut text, &format!(" ({})", names.len()), true);
+    }
+    (text, dimmed)
+}
+
+/// Draws `label` at `origin`, cut off at the edges of `clip`.
+fn draw_clipped(ctx: &mut PaintCtx, label: &Label<Thread>, clip: Rect, origin: Point) {
+    ctx.with_save(|ctx| {
+        ctx.clip(clip);
+        label.draw_at(ctx, origin);
+    });
+}
+
+/// Where everything in a row goes, worked out in `layout` for `paint`.
+#[derive(Default)]
+struct RowLayout {
+    left: f64,
+    senders: Option<PietTextLayout>,
+    senders_width: f64,
+    star: Rect,
+    icons_x: f64,
+    count_x: f64,
+    date: Option<PietTextLayout>,
+    date_x: f64,
+    subject_width: f64,
+    snippet_x: f64,
+    snippet_width: f64,
+    /// Each visible tag's text, its colour and where its pill goes
+    pills: Vec<(PietTextLayout, Color, Rect)>,
+}
 
 pub struct ThreadWidget {
-    timer_id: TimerToken,
-    senders: Option<Label<Thread>>,
-    subject: Option<Label<Thread>>,
-    date: Option<Label<Thread>>,
-    date_size: Size,
+    /// Goes off every so often to keep the date up to date
+    clock_timer: TimerToken,
+    tag_styles: Arc<TagStyles>,
+    /// The user's identities, whose names are shown as "me"
+    identities: Vector<Identity>,
+    icons: Label<Thread>,
+    count: Label<Thread>,
+    subject: Label<Thread>,
+    snippet: Label<Thread>,
+    row: RowLayout,
 }
 
 impl ThreadWidget {
-    pub fn new() -> Self {
+    pub fn new(tag_styles: Arc<TagStyles>, identities: Vector<Identity>) -> Self {
         Self {
-            timer_id: TimerToken::INVALID,
-            senders: None,
-            subject: None,
-            date: None,
-            date_size: Size::ZERO,
+            clock_timer: TimerToken::INVALID,
+            tag_styles,
+            identities,
+            icons: Label::new(|mail: &Thread, _env: &Env| indicators(mail))
+                .with_font(METADATA_FONT)
+                .with_text_color(SECONDARY_TEXT_COLOR),
+            count: Label::new(|mail: &Thread, _env: &Env| message_count(mail))
+                .with_font(METADATA_FONT)
+                .with_text_color(SECONDARY_TEXT_COLOR),
+            subject: Label::new(|mail: &Thread, _env: &Env| mail.subject.to_string())
+                .with_text_alignment(TextAlignment::Start)
+                .with_font(crate::UI_FONT_LIGHT)
+                .with_line_break_mode(LineBreaking::Clip),
+            snippet: Label::new(|mail: &Thread, _env: &Env| {
+                if mail.snippet.is_empty() {
+                    String::new()
+                } else {
+                    format!("— {}", mail.snippet)
+                }
+            })
+            .with_font(crate::UI_FONT_LIGHT)
+            .with_text_color(SECONDARY_TEXT_COLOR)
+            .with_line_break_mode(LineBreaking::Clip),
+            row: RowLayout::default(),
         }
     }
+
+    fn labels(&mut self) -> [&mut Label<Thread>; 4] {
+        [
+            &mut self.icons,
+            &mut self.count,
+            &mut self.subject,
+            &mut self.snippet,
+        ]
+    }
+
+    /// When the thread's newest message came, relative to now, or when it wakes if it's
+    /// snoozed.
+    fn date(&self, ctx: &mut druid::LayoutCtx, thread: &Thread, env: &Env) -> PietTextLayout {
+        let font = env.get(METADATA_FONT);
+        let dates = DATE_FORMAT.read().unwrap();
+        // Snoozed threads show when they'll be back instead
+        let text = match &thread.snoozed_until {
+            Some(until) => format!("⏰ {}", dates.long(&**until)),
+            None => dates.relative(&*thread.date, &Local::now()),
+        };
+        ctx.text()
+            .new_text_layout(text)
+            .font(font.family.clone(), font.size)
+            .default_attribute(TextAttribute::Weight(font.weight))
+            .text_color(env.get(theme::TEXT_COLOR))
+            .build()
+            .unwrap()
+    }
+
+    /// The authors, matched ones in the usual text colour and the rest dimmed, with as
+    /// many left out of the middle as it takes to fit in `width`.
+    fn senders(
+        &self,
+        ctx: &mut druid::LayoutCtx,
+        thread: &Thread,
+        width: f64,
+        env: &Env,
+    ) -> PietTextLayout {
+        let font = env.get(crate::UI_FONT);
+        let names = author_names(&thread.authors, &self.identities);
+        let mut build = |keep: usize| {
+            let (text, dimmed) = join_names(&names, keep);
+            let mut builder = ctx
+                .text()
+                .new_text_layout(text)
+                .font(font.family.clone(), font.size)
+                .default_attribute(TextAttribute::Weight(font.weight))
+                .text_color(env.get(theme::TEXT_COLOR));
+            for range in dimmed {
+                builder = builder.range_attribute(
+                    range,
+                    TextAttribute::TextColor(env.get(SECONDARY_TEXT_COLOR)),
+                );
+            }
+            builder.build().unwrap()
+        };
+        let mut keep = names.len();
+        loop {
+            let layout = build(keep);
+            // A single name that doesn't fit is clipped instead
+            if keep <= 1 || layout.size().width <= width {
+                return layout;
+            }
+            keep = (keep.min(names.len() - 1) - 1).max(1);
+        }
+    }
+
+    /// The text and colour of a pill for each tag that isn't hidden, plus one marking
+    /// threads with excluded messages.
+    fn pills(
+        &self,
+        ctx: &mut druid::LayoutCtx,
+        thread: &Thread,
+        env: &Env,
+    ) -> Vec<(PietTextLayout, Color)> {
+        let font = env.get(METADATA_FONT);
+        let mut pill = |text: &str, color: Color| {
+            let layout = ctx
+                .text()
+                .new_text_layout(text.to_string())
+                .font(font.family.clone(), font.size)
+                .text_color(env.get(TAG_TEXT_COLOR))
+                .build()
+                .unwrap();
+            (layout, color)
+        };
+        let mut pills = Vec::new();
+        if thread.excluded {
+            pills.push(pill("excluded", env.get(SECONDARY_TEXT_COLOR)));
+        }
+        for tag in thread.tags.iter() {
+            if !self.tag_styles.is_hidden(tag) {
+                let color = self
+                    .tag_styles
+                    .color(tag)
+                    .cloned()
+                    .unwrap_or_else(|| env.get(TAG_COLOR));
+                pills.push(pill(tag, color));
+            }
+        }
+        pills
+    }
 }
 
 impl Widget<(Option<Arc<Thread>>, Arc<Thread>)> for ThreadWidget {
@@ -43,24 +334,26 @@ impl Widget<(Option<Arc<Thread>>, Arc<Thread>)> for ThreadWidget {
         _env: &Env,
     ) {
         match event {
-            Event::Timer(id) => {
-                // Check if the timer in question is for us, and that this thread is still being viewed
-                if *id == self.timer_id
-                    && data.0.is_some()
-                    && data.0.as_ref().unwrap().id == data.1.id
-                {
-                    // If so, remove the "unread" tag
-                    ctx.submit_command(MARK_READ.with(data.1.clone()));
-                    let mut new_thread = (*data.1).clone().to_owned();
-                    new_thread.tags.retain(|item| item != &"unread".to_string());
-                    data.1 = Arc::new(new_thread);
-                    ctx.request_paint();
-                }
+            Event::Timer(id) if *id == self.clock_timer => {
+                // The date is worked out again whenever the row is laid out
+                ctx.request_layout();
+                self.clock_timer = ctx.request_timer(REFRESH_INTERVAL);
+            }
+            Event::MouseUp(evt)
+                if evt.button == MouseButton::Left && self.row.star.contains(evt.pos) =>
+            {
+                let (flag, set) = star_click(&data.1);
+                let command = if flag { FLAG } else { UNFLAG };
+                ctx.submit_command(command.with(set));
+                ctx.set_handled();
             }
             Event::MouseUp(evt) => {
-                if evt.button == MouseButton::Left {
-                    ctx.submit_command(LOAD_THREAD.with(data.1.clone()));
-                    self.timer_id = ctx.request_timer(Duration::from_secs(2));
+                // Middle-click or ctrl-click opens the thread in a new tab
+                let new_tab = evt.button == MouseButton::Middle
+                    || (evt.button == MouseButton::Left && evt.mods.ctrl());
+                if new_tab || evt.button == MouseButton::Left {
+                    let command = if new_tab { OPEN_TAB } else { LOAD_THREAD };
+                    ctx.submit_command(command.with(data.1.clone()));
                     ctx.request_paint();
                 }
             }
@@ -75,48 +368,11 @@ impl Widget<(Option<Arc<Thread>>, Arc<Thread>)> for ThreadWidget {
         data: &(Option<Arc<Thread>>, Arc<Thread>),
         env: &Env,
     ) {
-        match event {
-            LifeCycle::WidgetAdded => {
-                self.senders = Some(
-                    Label::new(|mail: &Thread, _env: &Env| mail.authors.iter().join(", "))
-                        .with_text_color(Color::BLACK)
-                        .with_font(crate::UI_FONT)
-                        .with_text_alignment(TextAlignment::Start)
-                        .with_line_break_mode(LineBreaking::Clip),
-                );
-                self.subject = Some(
-                    Label::new(|mail: &Thread, _env: &Env| mail.subject.to_string())
-                        .with_text_color(Color::BLACK)
-                        .with_text_alignment(TextAlignment::Start)
-                        .with_font(crate::UI_FONT_LIGHT),
-                );
-                self.date = Some(
-                    Label::new(|mail: &Thread, _env: &Env| {
-                        let hours_ago = Local::now().signed_duration_since(*mail.date).num_hours();
-                        if hours_ago < 24 {
-                            format!("{} hours ago", hours_ago)
-                        } else {
-                            format!("{}", mail.date.format("%B %e, %Y"))
-                        }
-                    })
-                    .with_text_color(Color::BLACK)
-                    .with_font(crate::UI_FONT_LIGHT)
-                    .with_text_size(11.0),
-                );
-                self.senders
-                    .as_mut()
-                    .unwrap()
-                    .lifecycle(ctx, event, &data.1, env);
-                self.subject
-                    .as_mut()
-                    .unwrap()
-                    .lifecycle(ctx, event, &data.1, env);
-                self.date
-                    .as_mut()
-                    .unwrap()
-                    .lifecycle(ctx, event, &data.1, env);
+        if let LifeCycle::WidgetAdded = event {
+            for label in self.labels().iter_mut() {
+                label.lifecycle(ctx, event, &data.1, env);
             }
-            _ => {}
+            self.clock_timer = ctx.request_timer(REFRESH_INTERVAL);
         }
     }
 
@@ -127,18 +383,17 @@ impl Widget<(Option<Arc<Thread>>, Arc<Thread>)> for ThreadWidget {
         data: &(Option<Arc<Thread>>, Arc<Thread>),
         env: &Env,
     ) {
-        self.senders
-            .as_mut()
-            .unwrap()
-            .update(ctx, &old_data.1, &data.1, env);
-        self.subject
-            .as_mut()
-            .unwrap()
-            .update(ctx, &old_data.1, &data.1, env);
-        self.date
-            .as_mut()
-            .unwrap()
-            .update(ctx, &old_data.1, &data.1, env);
+        for label in self.labels().iter_mut() {
+            label.update(ctx, &old_data.1, &data.1, env);
+        }
+        // The date, senders and pills are laid out along with everything else
+        if !old_data.1.date.same(&data.1.date)
+            || !old_data.1.tags.same(&data.1.tags)
+            || old_data.1.excluded != data.1.excluded
+            || !old_data.1.authors.same(&data.1.authors)
+        {
+            ctx.request_layout();
+        }
     }
 
     fn layout(
@@ -148,17 +403,76 @@ impl Widget<(Option<Arc<Thread>>, Arc<Thread>)> for ThreadWidget {
         data: &(Option<Arc<Thread>>, Arc<Thread>),
         env: &Env,
     ) -> druid::Size {
-        self.date_size = self.date.as_mut().unwrap().layout(ctx, bc, &data.1, env);
-        let senders_bc = druid::BoxConstraints::new(
-            bc.min().clone(),
-            Size::new(bc.max().width - self.date_size.width, bc.max().height),
-        ); // Clip the senders if they're going to overlap the sent date
-        self.senders
-            .as_mut()
-            .unwrap()
-            .layout(ctx, &senders_bc, &data.1, env);
-        self.subject.as_mut().unwrap().layout(ctx, bc, &data.1, env);
-        druid::Size::new(bc.max().width, THREAD_HEIGHT)
+        let thread = &data.1;
+        let size = Size::new(bc.max().width, env.get(THREAD_HEIGHT));
+        let mut row = RowLayout {
+            left: size.height * 0.1 * 4.3,
+            ..RowLayout::default()
+        };
+
+        // The top line fills in from the right: date, message count, icons and star,
+        // with the senders clipped to whatever is left
+        let date = self.date(ctx, thread, env);
+        let date_height = date.size().height;
+        row.date_x = size.width - date.size().width;
+        row.date = Some(date);
+        let count = self.count.layout(ctx, bc, thread, env);
+        row.count_x = row.date_x - count.width - if count.width > 0. { GAP } else { 0. };
+        let icons = self.icons.layout(ctx, bc, thread, env);
+        row.icons_x = row.count_x - icons.width - if icons.width > 0. { GAP } else { 0. };
+        let star_x = row.icons_x - STAR_SIZE - if icons.width > 0. { GAP } else { 0. };
+        let star_y = size.height * 0.2 + (date_height - STAR_SIZE) / 2.;
+        row.star = Rect::from_origin_size((star_x, star_y), (STAR_SIZE, STAR_SIZE));
+        row.senders_width = (row.star.x0 - GAP - row.left).max(0.);
+        row.senders = Some(self.senders(ctx, thread, row.senders_width, env));
+
+        // The bottom line has the tag pills on the right and the subject, then the
+        // snippet, in the space left of them
+        let subject_y = size.height * 0.5;
+        let mut pills_x = size.width;
+        for (layout, color) in self.pills(ctx, thread, env) {
+            let text = layout.size();
+            let pill = Size::new(
+                text.width + 2. * PILL_PADDING.width,
+                text.height + 2. * PILL_PADDING.height,
+            );
+            let spacing = if row.pills.is_empty() {
+                0.
+            } else {
+                PILL_SPACING
+            };
+            let x = pills_x - pill.width - spacing;
+            if size.width - x > size.width * MAX_PILLS_SHARE {
+                break;
+            }
+            pills_x = x;
+            row.pills.push((
+                layout,
+                color,
+                Rect::from_origin_size(Point::new(pills_x, subject_y), pill),
+            ));
+        }
+        let text_width = (pills_x - GAP - row.left).max(0.);
+        let subject_bc =
+            druid::BoxConstraints::new(Size::ZERO, Size::new(text_width, bc.max().height));
+        let subject = self.subject.layout(ctx, &subject_bc, thread, env);
+        row.subject_width = subject.width.min(text_width);
+        row.snippet_x = row.left + row.subject_width + GAP;
+        row.snippet_width = (row.left + text_width - row.snippet_x).max(0.);
+        let snippet_bc =
+            druid::BoxConstraints::new(Size::ZERO, Size::new(row.snippet_width, bc.max().height));
+        self.snippet.layout(ctx, &snippet_bc, thread, env);
+
+        // Centre the pills on the subject
+        for (_, _, rect) in row.pills.iter_mut() {
+            *rect = rect.with_origin(Point::new(
+                rect.x0,
+                subject_y + (subject.height - rect.height()) / 2.,
+            ));
+        }
+
+        self.row = row;
+        size
     }
 
     fn paint(
@@ -169,6 +483,7 @@ impl Widget<(Option<Arc<Thread>>, Arc<Thread>)> for ThreadWidget {
     ) {
         let size = ctx.size();
         let rect = size.to_rect();
+        let row = &self.row;
         let bg_color = if data.0.is_some() && data.0.as_ref().unwrap().id == data.1.id {
             &THREAD_SELECTED_COLOR
         } else {
@@ -176,48 +491,197 @@ impl Widget<(Option<Arc<Thread>>, Arc<Thread>)> for ThreadWidget {
         };
         ctx.fill(rect, &env.get(bg_color));
 
-        let radius = THREAD_HEIGHT * 0.1;
-        if data.1.tags.contains(&"unread".to_string()) {
+        let radius = size.height * 0.1;
+        if data.1.tags.contains(&"unread".to_string()) && !data.1.is_muted() {
             ctx.fill(
                 Circle::new(Point::new(14., size.height * 0.5), radius),
-                &Color::rgb8(20, 217, 235),
+                &env.get(UNREAD_COLOR),
             );
         }
 
-        self.senders
-            .as_ref()
-            .unwrap()
-            .draw_at(ctx, Point::new(radius * 4.3, size.height * 0.2));
-
-        self.subject
-            .as_ref()
-            .unwrap()
-            .draw_at(ctx, Point::new(radius * 4.3, size.height * 0.5));
+        let top = size.height * 0.2;
+        let bottom = size.height * 0.5;
+        if let Some(senders) = &row.senders {
+            ctx.with_save(|ctx| {
+                ctx.clip(Rect::new(
+                    row.left,
+                    0.,
+                    row.left + row.senders_width,
+                    size.height,
+                ));
+                ctx.draw_text(senders, Point::new(row.left, top));
+            });
+        }
+        paint_star(ctx, row.star, is_flagged(&data.1), env);
+        self.icons.draw_at(ctx, Point::new(row.icons_x, top));
+        self.count.draw_at(ctx, Point::new(row.count_x, top));
+        if let Some(date) = &row.date {
+            ctx.draw_text(date, Point::new(row.date_x, top));
+        }
 
-        ctx.fill(
+        draw_clipped(
+            ctx,
+            &self.subject,
+            Rect::new(row.left, 0., row.left + row.subject_width, size.height),
+            Point::new(row.left, bottom),
+        );
+        draw_clipped(
+            ctx,
+            &self.snippet,
             Rect::new(
-                size.width - self.date_size.width,
-                size.height * 0.2,
-                size.width,
-                (size.height * 0.2) + 15.,
+                row.snippet_x,
+                0.,
+                row.snippet_x + row.snippet_width,
+                size.height,
             ),
-            &env.get(bg_color),
+            Point::new(row.snippet_x, bottom),
         );
 
-        self.date.as_ref().unwrap().draw_at(
-            ctx,
-            Point::new(size.width - self.date_size.width, size.height * 0.2),
-        );
+        for (layout, color, pill) in row.pills.iter() {
+            ctx.fill(pill.to_rounded_rect(3.), color);
+            ctx.draw_text(
+                layout,
+                Point::new(pill.x0 + PILL_PADDING.width, pill.y0 + PILL_PADDING.height),
+            );
+        }
     }
 }
 
-pub fn thread_list() -> impl Widget<MailData> {
-    let widget_lens = (lens!(MailData, loaded_thread), lens!(MailData, threads));
+/// Re-runs the current search whenever the "show excluded" checkbox changes.
+struct ExcludedToggle;
+
+impl<W: Widget<bool>> Controller<bool, W> for ExcludedToggle {
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &bool,
+        data: &bool,
+        env: &Env,
+    ) {
+        if old_data != data {
+            ctx.submit_command(SHOW_EXCLUDED.with(*data));
+        }
+        child.update(ctx, old_data, data, env)
+    }
+}
+
+pub fn thread_list(
+    tag_styles: Arc<TagStyles>,
+    identities: Vector<Identity>,
+) -> impl Widget<MailData> {
+    let current_thread = lens!(MailData, tabs).map(
+        |tabs: &Tabs| tabs.current().cloned(),
+        |_tabs: &mut Tabs, _current: Option<Arc<Thread>>| {},
+    );
+    let widget_lens = (current_thread, lens!(MailData, threads));
     Flex::column()
         .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_flex_child(
+                    Autocomplete::new(CompletionMode::Query)
+                        .with_placeholder("Search mail")
+                        .on_submit(|ctx, query: &mut String, _env| {
+                            if !query.trim().is_empty() {
+                                ctx.submit_command(SEARCH_CHANGE.with(ArcStr::from(query.trim())));
+                            }
+                        })
+                        .expand_width()
+                        .lens(MailData::query),
+                    1.0,
+                )
+                .with_spacer(6.)
+                .with_child(
+                    Checkbox::new("Show excluded")
+                        .controller(ExcludedToggle)
+                        .lens(MailData::show_excluded),
+                ),
+        )
+        .with_child(
+            Label::new(|data: &MailData, _env: &Env| data.status.clone())
+                .with_text_color(ERROR_COLOR)
+                .with_line_break_mode(LineBreaking::WordWrap),
+        )
         .with_flex_child(
-            VirtList::vertical(THREAD_HEIGHT, || ThreadWidget::new()).lens(widget_lens),
+            VirtList::vertical(THREAD_HEIGHT, move || {
+                ThreadWidget::new(tag_styles.clone(), identities.clone())
+            })
+            .lens(widget_lens),
             1.0,
         )
         .padding(0.5)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn address(name: &str, email: &str) -> Address {
+        Address::new(Some(name.to_string()), email)
+    }
+
+    fn me() -> Vector<Identity> {
+        Vector::unit(Identity {
+            name: "Ada Lovelace".to_string(),
+            address: "ada@example.com".to_string(),
+            ..Identity::default()
+        })
+    }
+
+    #[test]
+    fn authors_who_matched_come_first() {
+        let authors = Authors::from_messages(&[
+            (address("Grace Hopper", "grace@example.com"), false),
+            (address("Ada", "ADA@example.com"), true),
+            (Address::new(None, "bob@example.com"), true),
+            (address("Grace", "grace@example.com"), true),
+        ]);
+        assert_eq!(
+            author_names(&authors, &me()),
+            [
+                ("me".to_string(), true),
+                ("bob@example.com".to_string(), true),
+                ("Grace".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_author_lists_are_cut_short() {
+        let names: Vec<(String, bool)> = ["Ann", "Bea", "Cy", "Dee", "Eve"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), i != 1))
+            .collect();
+        let (text, dimmed) = join_names(&names, 2);
+        assert_eq!(text, "Ann, Bea … Eve (5)");
+        let dimmed: Vec<&str> = dimmed.into_iter().map(|range| &text[range]).collect();
+        assert_eq!(dimmed, ["Bea", " … ", " (5)"]);
+
+        let (text, dimmed) = join_names(&names[..3], 2);
+        assert_eq!(text, "Ann, Bea, Cy");
+        assert_eq!(dimmed.len(), 1);
+    }
+
+    #[test]
+    fn the_star_flags_the_newest_match_and_unflags_every_flagged_message() {
+        let mut thread = (*testing::fixture_threads()[0]).clone();
+        for (summary, matched) in thread.summaries.iter_mut().zip(&[true, true, false]) {
+            summary.matched = *matched;
+        }
+        let (flag, set) = star_click(&thread);
+        assert!(flag);
+        assert_eq!(set.messages, ["lunch-reply@example.com"]);
+
+        thread.set_tag(&["lunch@example.com".to_string()], "flagged", true);
+        thread.set_tag(&["lunch-reply-2@example.com".to_string()], "flagged", true);
+        let (flag, set) = star_click(&thread);
+        assert!(!flag);
+        assert_eq!(
+            set.messages,
+            ["lunch@example.com", "lunch-reply-2@example.com"]
+        );
+    }
+}
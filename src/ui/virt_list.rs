@@ -26,34 +26,50 @@ use druid::kurbo::{Point, Rect, Size, Vec2};
 use druid::scroll_component::ScrollComponent;
 use druid::widget::ClipBox;
 use druid::{
-    BoxConstraints, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx,
-    Selector, UpdateCtx, Widget, WidgetPod,
+    BoxConstraints, Data, Env, Event, EventCtx, KeyOrValue, LayoutCtx, LifeCycle, LifeCycleCtx,
+    PaintCtx, Selector, UpdateCtx, Widget, WidgetPod,
 };
 
 /// A virtualized scrolling widget for a (possibly large) collection of items.
 pub struct VirtList<C, T> {
     clip: ClipBox<C, VirtListInner<T>>,
     scroll_component: ScrollComponent,
+    child_height: KeyOrValue<f64>,
 }
 
 impl<C: RangeIter<T>, T: Data> VirtList<C, T> {
     /// Create a new vertical list widget. Closure will be called every time when a new child
-    /// needs to be constructed. Child height needs to be constant.
+    /// needs to be constructed. All children have the same height, which may come from the
+    /// `Env` so that it can follow font sizes.
     pub fn vertical<W: Widget<T> + 'static>(
-        child_height: f64,
+        child_height: impl Into<KeyOrValue<f64>>,
         closure: impl Fn() -> W + 'static,
     ) -> Self {
-        let inner = VirtListInner::new(
-            Size::new(0.0, child_height),
-            Box::new(move || Box::new(closure())),
-        );
+        let inner = VirtListInner::new(Size::ZERO, Box::new(move || Box::new(closure())));
         Self {
             clip: ClipBox::managed(inner)
                 .constrain_vertical(false)
                 .constrain_horizontal(true),
             scroll_component: ScrollComponent::new(),
+            child_height: child_height.into(),
         }
     }
+
+    /// Picks up a new child height, recounting the visible children. Returns `true` if the
+    /// height changed.
+    fn resolve_child_height(&mut self, env: &Env) -> bool {
+        let height = self.child_height.resolve(env);
+        let viewport_size = self.clip.viewport_size();
+        let offset = self.clip.viewport_origin().to_vec2();
+        let inner = self.clip.child_mut();
+        if inner.child_size.height == height {
+            return false;
+        }
+        inner.child_size.height = height;
+        inner.set_viewport_size(viewport_size);
+        inner.set_viewport_offset(offset);
+        true
+    }
 }
 
 impl<C: RangeIter<T>, T: Data> Widget<C> for VirtList<C, T> {
@@ -82,6 +98,9 @@ impl<C: RangeIter<T>, T: Data> Widget<C> for VirtList<C, T> {
     }
 
     fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &C, env: &Env) {
+        if let LifeCycle::WidgetAdded = &event {
+            self.resolve_child_height(env);
+        }
         if let LifeCycle::Size(_) = &event {
             // The size of the viewport has changed, `VirtListInner` will take care of
             // adding/removing children.
@@ -97,6 +116,11 @@ impl<C: RangeIter<T>, T: Data> Widget<C> for VirtList<C, T> {
     }
 
     fn update(&mut self, ctx: &mut UpdateCtx, old_data: &C, data: &C, env: &Env) {
+        if ctx.env_key_changed(&self.child_height) && self.resolve_child_height(env) {
+            let child_id = self.clip.child_pod().id();
+            ctx.submit_command(VIEWPORT_SIZE_CHANGED.to(child_id));
+            ctx.request_layout();
+        }
         self.clip.update(ctx, old_data, data, env);
     }
 